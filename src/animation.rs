@@ -0,0 +1,340 @@
+use crate::base::*;
+use crate::world::*;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// the animations used by the characters that have none of their own
+pub const DEFAULT_ANIMATIONS: &str = "default";
+// the animations of the player
+pub const PLAYER_ANIMATIONS: &str = "Anthea";
+// how long the interaction animation plays
+pub const INTERACT_DELAY: u64 = 400;
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Facing {
+    Up,
+    #[default]
+    Down,
+    Left,
+    Right,
+}
+
+impl Facing {
+    /// The direction of a move, favouring the horizontal one for diagonal moves
+    pub fn from_move(from: &SpritePosition, to: &SpritePosition) -> Self {
+        if to.x < from.x {
+            Facing::Left
+        } else if to.x > from.x {
+            Facing::Right
+        } else if to.y < from.y {
+            Facing::Up
+        } else {
+            Facing::Down
+        }
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum AnimationState {
+    #[default]
+    Idle,
+    Walk(Facing),
+    Interact,
+}
+
+/// One frame: how the sprites are moved and flipped, and which image a character shows
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    #[serde(default)]
+    pub offset: (f32, f32),
+    #[serde(default)]
+    pub flip_x: bool,
+    // the image to show instead of the character's own, the player layers keep theirs
+    #[serde(default)]
+    pub sprite: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameSequence {
+    pub frame_time: u64,
+    pub frames: Vec<Frame>,
+}
+
+/// The frame sequences of each character, by name
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "3f0c1d5e-8a4b-4f57-9b1e-2d6c7a9e0b41"]
+pub struct AnimationSet {
+    pub characters: HashMap<String, HashMap<AnimationState, FrameSequence>>,
+}
+
+impl AnimationSet {
+    fn load(data: &[u8]) -> Result<AnimationSet, anyhow::Error> {
+        Ok(ron::de::from_bytes(data)?)
+    }
+
+    /// The frames to play for a character in a given state, falling back on the default ones,
+    /// then on standing still
+    pub fn sequence(&self, name: &str, state: &AnimationState) -> Option<&FrameSequence> {
+        let find = |n: &str, s: &AnimationState| self.characters.get(n).and_then(|m| m.get(s));
+        find(name, state)
+            .or_else(|| find(DEFAULT_ANIMATIONS, state))
+            .or_else(|| find(name, &AnimationState::Idle))
+            .or_else(|| find(DEFAULT_ANIMATIONS, &AnimationState::Idle))
+    }
+}
+
+#[derive(Default)]
+pub struct AnimationSetAssetLoader;
+
+impl AssetLoader for AnimationSetAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let set_asset = AnimationSet::load(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(set_asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["animations.ron"]
+    }
+}
+
+/// The animation of a character, or of all the layers of the player at once
+#[derive(Debug, Default, Clone, PartialEq, Eq, Component)]
+pub struct Animation {
+    pub name: String,
+    pub state: AnimationState,
+    pub frame: usize,
+    pub elapsed: u64,
+    // time left before going back to idle
+    pub hold: u64,
+}
+
+impl Animation {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Play the given state, for the given time if not zero
+    pub fn play(&mut self, state: AnimationState, hold: u64) -> &mut Self {
+        if self.state != state {
+            self.state = state;
+            self.frame = 0;
+            self.elapsed = 0;
+        }
+        self.hold = hold;
+        self
+    }
+
+    /// Move the animation forward in time, returning the frame to show
+    pub fn advance<'a>(&mut self, delta: u64, set: &'a AnimationSet) -> Option<&'a Frame> {
+        if self.hold > 0 {
+            if self.hold <= delta {
+                self.play(AnimationState::Idle, 0);
+            } else {
+                self.hold -= delta;
+            }
+        }
+        let seq = set.sequence(&self.name, &self.state)?;
+        if seq.frames.is_empty() {
+            return None;
+        }
+        self.elapsed += delta;
+        // a frame time of zero shows the first frame forever
+        if let Some(frames) = self.elapsed.checked_div(seq.frame_time) {
+            self.frame += frames as usize;
+            self.elapsed %= seq.frame_time;
+        }
+        self.frame %= seq.frames.len();
+        seq.frames.get(self.frame)
+    }
+}
+
+/// Walk while the player steps, and play the interaction when bumping into something
+pub fn player_animation_system(
+    step: Res<StepState>,
+    mut ev_affordance: EventReader<AffordanceEvent>,
+    mut ev_character: EventReader<CharacterEvent>,
+    mut query: Query<&mut Animation, With<Player>>,
+) {
+    let interact = ev_affordance.iter().count() + ev_character.iter().count() > 0;
+    for mut anim in query.iter_mut() {
+        if interact {
+            anim.play(AnimationState::Interact, INTERACT_DELAY);
+        } else if let Some(current) = &step.current {
+            anim.play(
+                AnimationState::Walk(Facing::from_move(&current.from, &current.to)),
+                0,
+            );
+        } else if anim.hold == 0 {
+            anim.play(AnimationState::Idle, 0);
+        }
+    }
+}
+
+/// Show the current frame of every animation: the player layers all move together
+pub fn animation_system(
+    time: Res<Time>,
+    handles: Res<AntheaHandles>,
+    sets: Res<Assets<AnimationSet>>,
+    asset_server: Res<AssetServer>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut anim_query: Query<(
+        &mut Animation,
+        Option<&Children>,
+        Option<&Character>,
+        &mut Transform,
+        Option<&mut TextureAtlasSprite>,
+        Option<&Handle<TextureAtlas>>,
+    )>,
+    mut part_query: Query<
+        (&mut Transform, &mut TextureAtlasSprite),
+        (With<PlayerPart>, Without<Animation>),
+    >,
+) {
+    let Some(set) = sets.get(&handles.animations_handle) else {
+        return;
+    };
+    let delta = time.delta().as_millis() as u64;
+    for (mut anim, children, chr, mut transform, sprite, atlas) in anim_query.iter_mut() {
+        let Some(frame) = anim.advance(delta, set) else {
+            continue;
+        };
+        if let Some(chr) = chr {
+            let pos = chr.position.to_vec3();
+            transform.translation.x = pos.x + frame.offset.0;
+            transform.translation.y = pos.y + frame.offset.1;
+        }
+        if let Some(mut sprite) = sprite {
+            sprite.flip_x = frame.flip_x;
+            if let Some(index) = chr.zip(atlas).and_then(|(chr, atlas)| {
+                let image = frame.sprite.as_deref().unwrap_or(&chr.sprite);
+                atlases
+                    .get(atlas)?
+                    .get_texture_index(&asset_server.get_handle(image))
+            }) {
+                sprite.index = index;
+            }
+        }
+        for child in children.into_iter().flatten() {
+            if let Ok((mut part_transform, mut part_sprite)) = part_query.get_mut(*child) {
+                part_transform.translation.x = frame.offset.0;
+                part_transform.translation.y = frame.offset.1;
+                part_sprite.flip_x = frame.flip_x;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set() -> AnimationSet {
+        AnimationSet::load(
+            br#"(
+                characters: {
+                    "default": {
+                        Idle: (frame_time: 100, frames: [(), (offset: (0.0, 1.0))]),
+                        Walk(Left): (frame_time: 50, frames: [(flip_x: true)]),
+                    },
+                    "cat": {
+                        Idle: (frame_time: 0, frames: [(offset: (2.0, 0.0))]),
+                        Interact: (frame_time: 100, frames: [(), (sprite: Some("cat2.png"))]),
+                    },
+                },
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load() -> Result<(), anyhow::Error> {
+        let data = std::fs::read("assets/anthea.animations.ron")?;
+        let set = AnimationSet::load(&data)?;
+        assert!(set
+            .sequence(PLAYER_ANIMATIONS, &AnimationState::Interact)
+            .is_some());
+        assert!(set
+            .sequence("someone", &AnimationState::Walk(Facing::Up))
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_fallback() {
+        let set = set();
+        let cat = set.sequence("cat", &AnimationState::Idle).unwrap();
+        assert_eq!((2.0, 0.0), cat.frames[0].offset);
+        // no walk for the cat, use the default one
+        let walk = set
+            .sequence("cat", &AnimationState::Walk(Facing::Left))
+            .unwrap();
+        assert!(walk.frames[0].flip_x);
+        // no right walk at all, the cat stays idle
+        let right = set
+            .sequence("cat", &AnimationState::Walk(Facing::Right))
+            .unwrap();
+        assert_eq!(cat, right);
+        let interact = set.sequence("cat", &AnimationState::Interact).unwrap();
+        assert_eq!(None, interact.frames[0].sprite);
+        assert_eq!(Some("cat2.png".to_string()), interact.frames[1].sprite);
+    }
+
+    #[test]
+    fn test_advance() {
+        let set = set();
+        let mut anim = Animation::new("someone");
+        assert_eq!((0.0, 0.0), anim.advance(50, &set).unwrap().offset);
+        assert_eq!((0.0, 1.0), anim.advance(60, &set).unwrap().offset);
+        assert_eq!((0.0, 0.0), anim.advance(100, &set).unwrap().offset);
+        anim.play(AnimationState::Walk(Facing::Left), 0);
+        assert_eq!(0, anim.frame);
+        assert!(anim.advance(20, &set).unwrap().flip_x);
+    }
+
+    #[test]
+    fn test_hold() {
+        let set = set();
+        let mut anim = Animation::new("someone");
+        anim.play(AnimationState::Walk(Facing::Left), 100);
+        assert!(anim.advance(60, &set).unwrap().flip_x);
+        assert!(!anim.advance(60, &set).unwrap().flip_x);
+        assert_eq!(AnimationState::Idle, anim.state);
+    }
+
+    #[test]
+    fn test_facing() {
+        let from = SpritePosition::new(1, 1);
+        assert_eq!(
+            Facing::Left,
+            Facing::from_move(&from, &SpritePosition::new(0, 2))
+        );
+        assert_eq!(
+            Facing::Up,
+            Facing::from_move(&from, &SpritePosition::new(1, 0))
+        );
+        assert_eq!(
+            Facing::Down,
+            Facing::from_move(&from, &SpritePosition::new(1, 2))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::base::*;
+use crate::creation::CreationOptions;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use strum::IntoEnumIterator;
+
+// the sprite of a slot with nothing in it
+pub const EMPTY_SPRITE: &str = "sprites/people/empty.png";
+
+/// What an item looks like when the player has it equipped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Equipment {
+    pub part: PlayerPart,
+    pub sprite: String,
+}
+
+/// The look of the player: a sprite per part, the sprites of the items that can be equipped,
+/// and what can be changed when creating the character
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "9b7d3c52-61e4-4a0f-8d2a-5c1f0e7b4a93"]
+pub struct Appearance {
+    pub parts: BTreeMap<PlayerPart, String>,
+    #[serde(default)]
+    pub equipment: HashMap<String, Equipment>,
+    #[serde(default)]
+    pub creation: CreationOptions,
+}
+
+impl Appearance {
+    fn load(data: &[u8]) -> Result<Appearance, anyhow::Error> {
+        Ok(ron::de::from_bytes(data)?)
+    }
+
+    /// The sprite of a part without any equipment
+    pub fn base_sprite(&self, part: &PlayerPart) -> &str {
+        self.parts
+            .get(part)
+            .map(|s| s.as_str())
+            .unwrap_or(EMPTY_SPRITE)
+    }
+
+    /// The sprite of a part, showing the item equipped in it if any
+    pub fn sprite(&self, part: &PlayerPart, inventory: &Inventory) -> String {
+        inventory
+            .equipped_in(part)
+            .and_then(|name| self.equipment.get(name))
+            .filter(|eq| &eq.part == part)
+            .map(|eq| eq.sprite.clone())
+            .unwrap_or_else(|| self.base_sprite(part).to_string())
+    }
+
+    /// The parts that equipment can go in
+    pub fn equipment_parts(&self) -> Vec<PlayerPart> {
+        let mut parts: Vec<PlayerPart> =
+            self.equipment.values().map(|eq| eq.part.clone()).collect();
+        parts.sort();
+        parts.dedup();
+        parts
+    }
+}
+
+#[derive(Default)]
+pub struct AppearanceAssetLoader;
+
+impl AssetLoader for AppearanceAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let appearance_asset = Appearance::load(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(appearance_asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["appearance.ron"]
+    }
+}
+
+/// The sprite index to use for each part of the player, in spawning order
+pub fn part_indices(
+    appearance: &Appearance,
+    inventory: &Inventory,
+    asset_server: &AssetServer,
+    texture_atlas: &TextureAtlas,
+) -> Vec<(PlayerPart, usize)> {
+    PlayerPart::iter()
+        .filter_map(|part| {
+            let sprite = appearance.sprite(&part, inventory);
+            let handle = asset_server.get_handle(sprite.as_str());
+            match texture_atlas.get_texture_index(&handle) {
+                Some(index) => Some((part, index)),
+                None => {
+                    eprintln!("Could not find handle for {}", sprite);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Show the equipped items on the player when the inventory changes
+pub fn equipment_system(
+    inventory: Res<Inventory>,
+    handles: Res<AntheaHandles>,
+    appearances: Res<Assets<Appearance>>,
+    asset_server: Res<AssetServer>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut body_change: EventWriter<BodyChangeEvent>,
+    part_query: Query<(&TextureAtlasSprite, &Handle<TextureAtlas>, &PlayerPart)>,
+) {
+    if !inventory.is_changed() {
+        return;
+    }
+    let Some(appearance) = appearances.get(&handles.appearance_handle) else {
+        return;
+    };
+    let parts = appearance.equipment_parts();
+    for (sprite, atlas_handle, part) in part_query.iter().filter(|(_, _, p)| parts.contains(p)) {
+        let Some(texture_atlas) = texture_atlases.get(atlas_handle) else {
+            continue;
+        };
+        let wanted = appearance.sprite(part, &inventory);
+        let handle = asset_server.get_handle(wanted.as_str());
+        if texture_atlas.get_texture_index(&handle) != Some(sprite.index) {
+            body_change.send(BodyChangeEvent::new(part.clone(), wanted));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() -> Result<(), anyhow::Error> {
+        let data = std::fs::read("assets/anthea.appearance.ron")?;
+        let appearance = Appearance::load(&data)?;
+        for part in PlayerPart::iter() {
+            assert!(appearance.parts.contains_key(&part), "{:?}", part);
+        }
+        assert_eq!(
+            PlayerPart::RightHand,
+            appearance.equipment.get("sword").unwrap().part
+        );
+        assert!(appearance.creation.talent_points > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_equipped_sprite() {
+        let appearance = Appearance::load(
+            br#"(
+                parts: {
+                    Hair: "hair.png",
+                },
+                equipment: {
+                    "hat": (part: Head, sprite: "hat.png"),
+                },
+            )"#,
+        )
+        .unwrap();
+        let mut inventory = Inventory::default();
+        assert_eq!("hair.png", appearance.sprite(&PlayerPart::Hair, &inventory));
+        assert_eq!(
+            EMPTY_SPRITE,
+            appearance.sprite(&PlayerPart::Head, &inventory)
+        );
+        assert_eq!(vec![PlayerPart::Head], appearance.equipment_parts());
+
+        inventory.add_item(Item::new("hat", "A hat", "", 0, 0));
+        inventory.equip("hat", PlayerPart::Head);
+        assert_eq!("hat.png", appearance.sprite(&PlayerPart::Head, &inventory));
+        // the hat only goes on the head
+        inventory.equip("hat", PlayerPart::Feet);
+        assert_eq!(
+            EMPTY_SPRITE,
+            appearance.sprite(&PlayerPart::Feet, &inventory)
+        );
+
+        inventory.remove_item("hat");
+        assert_eq!(
+            EMPTY_SPRITE,
+            appearance.sprite(&PlayerPart::Head, &inventory)
+        );
+    }
+}
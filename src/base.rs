@@ -1,10 +1,14 @@
 use bevy::prelude::*;
 use bevy_asset_loader::asset_collection::AssetCollection;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use serde::{Deserialize, Serialize};
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use strum_macros::EnumIter;
 
+use crate::animation::AnimationSet;
+use crate::appearance::Appearance;
+use crate::experience::TalentRegistry;
 use crate::tiled::*;
 
 pub const SCREEN_WIDTH: i32 = 640;
@@ -12,7 +16,10 @@ pub const SCREEN_HEIGHT: i32 = 480;
 
 pub const SPRITE_SIZE: i32 = 32;
 
-pub const VISIBILITY_DISTANCE: f32 = 4.0 * SPRITE_SIZE as f32;
+pub const VISIBILITY_DISTANCE: i32 = 4;
+
+// tint of the tiles that were seen before but are not in sight anymore
+pub const REMEMBERED_COLOR: Color = Color::rgb(0.35, 0.35, 0.45);
 
 pub const MOVE_DELAY: u128 = 200;
 
@@ -38,6 +45,12 @@ pub struct AntheaHandles {
     pub tileset_handle: Handle<TileSet>,
     #[asset(path = "castle1.tmx")]
     pub map_handle: Handle<Map>,
+    #[asset(path = "anthea.animations.ron")]
+    pub animations_handle: Handle<AnimationSet>,
+    #[asset(path = "anthea.appearance.ron")]
+    pub appearance_handle: Handle<Appearance>,
+    #[asset(path = "anthea.talents.ron")]
+    pub talents_handle: Handle<TalentRegistry>,
     #[asset(path = "RPG_GUI_v1.png")]
     pub ui_handle: Handle<Image>,
     #[asset(path = "paper background.png")]
@@ -45,6 +58,8 @@ pub struct AntheaHandles {
     #[asset(path = "GRECOromanLubedWrestling.ttf")]
     pub font_handle: Handle<Font>,
     pub ui_texture_atlas_handle: Handle<TextureAtlas>,
+    // all the item sprites, built once they are loaded
+    pub items_texture_atlas_handle: Handle<TextureAtlas>,
     #[asset(path = "sounds", collection)]
     pub sound_handles: Vec<HandleUntyped>,
 }
@@ -55,10 +70,101 @@ pub struct AntheaState {
     pub map_position: SpritePosition,
     pub positions: HashMap<SpritePosition, TileEntityState>,
     pub revealed: HashSet<SpritePosition>,
-    pub last_move: u128,
     //pub last_hover: Option<SpritePosition>,
 }
 
+/// A step from one tile to the next, interpolated over `MOVE_DELAY`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Step {
+    pub from: SpritePosition,
+    pub to: SpritePosition,
+    pub elapsed: u128,
+}
+
+impl Step {
+    pub fn new(from: SpritePosition, to: SpritePosition) -> Self {
+        Self {
+            from,
+            to,
+            elapsed: 0,
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        (self.elapsed as f32 / MOVE_DELAY as f32).min(1.0)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.elapsed >= MOVE_DELAY
+    }
+}
+
+/// Transient movement state: the step in progress, and the next one queued by the player
+#[derive(Debug, Default, Clone, PartialEq, Eq, Resource)]
+pub struct StepState {
+    pub current: Option<Step>,
+    pub buffered: Option<SpritePosition>,
+    pub last_move: u128,
+}
+
+impl StepState {
+    pub fn is_moving(&self) -> bool {
+        self.current.is_some()
+    }
+}
+
+// real milliseconds per game hour: a full day lasts 24 minutes
+pub const GAME_HOUR: u64 = 60_000;
+// the hour of the day when the game starts
+pub const START_HOUR: u64 = 8;
+
+/// The time of day in the game, advancing while the game is running
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct GameClock {
+    // kept in a u64, the save format has no u128
+    pub elapsed: u64,
+}
+
+impl GameClock {
+    pub fn hour(&self) -> u32 {
+        ((self.elapsed / GAME_HOUR + START_HOUR) % 24) as u32
+    }
+
+    /// The day of the game, starting at 0
+    pub fn day(&self) -> u64 {
+        (self.elapsed / GAME_HOUR + START_HOUR) / 24
+    }
+}
+
+/// What the player did during the game
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct Stats {
+    pub steps: u32,
+    // milliseconds of play
+    pub play_time: u64,
+}
+
+/// How the game ended, set by the stage before going to the end screen
+#[derive(Debug, Default, Clone, PartialEq, Eq, Resource)]
+pub struct Ending {
+    pub title: String,
+    pub text: String,
+    // what the player did that is worth remembering
+    pub story: Vec<String>,
+}
+
+/// The preferences of the player, kept from one game to the next
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct Settings {
+    pub sound: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { sound: true }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash, States, Default)]
 pub enum GameState {
     #[default]
@@ -66,14 +172,17 @@ pub enum GameState {
     Title,
     Background,
     Start,
+    Creation,
     Running,
     Menu,
+    Map,
     Pause,
     Save,
     Clean,
     Reset,
     Load,
     End,
+    NewGame,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd, Resource)]
@@ -88,6 +197,13 @@ pub struct TileEntityState {
     pub entities: Vec<Entity>,
     pub passable: bool,
     pub transparent: bool,
+    // cost of walking over the tile when planning a path
+    #[serde(default = "default_cost")]
+    pub cost: u32,
+}
+
+fn default_cost() -> u32 {
+    1
 }
 
 impl Default for TileEntityState {
@@ -96,6 +212,7 @@ impl Default for TileEntityState {
             entities: vec![],
             passable: true,
             transparent: true,
+            cost: default_cost(),
         }
     }
 }
@@ -118,6 +235,7 @@ impl SpritePosition {
         }
     }
 
+    // map rows go down while the world y axis goes up
     pub fn to_vec3(&self) -> Vec3 {
         Self::to_vec3_z(self, 0.0)
     }
@@ -125,13 +243,13 @@ impl SpritePosition {
     pub fn to_vec3_z(&self, z: f32) -> Vec3 {
         Vec3::new(
             (self.x * SPRITE_SIZE) as f32,
-            (self.y * SPRITE_SIZE) as f32,
+            (-self.y * SPRITE_SIZE) as f32,
             z,
         )
     }
 
     pub fn from_vec3(v: &Vec3) -> SpritePosition {
-        Self::from_coords(v.x, v.y)
+        Self::from_coords(v.x, -v.y)
     }
 
     pub fn copy(&mut self, pos: &SpritePosition) {
@@ -156,6 +274,11 @@ impl SpritePosition {
     pub fn distance(&self, other: &SpritePosition) -> u32 {
         self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
     }
+
+    /// Whether the other position is one of the eight around this one
+    pub fn is_adjacent(&self, other: &SpritePosition) -> bool {
+        self != other && self.x.abs_diff(other.x) <= 1 && self.y.abs_diff(other.y) <= 1
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -190,10 +313,22 @@ impl SpriteDimension {
     }
 }
 
+/// The positions left to walk to, last one first, and what to interact with once next to it
 #[derive(
     Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Resource,
 )]
-pub struct MovementPlan(pub Vec<SpritePosition>);
+pub struct MovementPlan {
+    pub steps: Vec<SpritePosition>,
+    pub target: Option<SpritePosition>,
+}
+
+impl MovementPlan {
+    pub fn clear(&mut self) -> &mut Self {
+        self.steps.clear();
+        self.target = None;
+        self
+    }
+}
 
 #[derive(
     Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Component,
@@ -205,12 +340,33 @@ pub struct MapTile(pub usize);
 )]
 pub enum PlayerPart {
     Body,
+    Feet,
     Pants,
     Top,
+    Cloak,
     Hair,
+    Head,
+    LeftHand,
     RightHand,
 }
 
+impl PlayerPart {
+    /// The depth of the part sprite, so that the later parts are drawn over the earlier ones
+    pub fn z(&self) -> f32 {
+        match self {
+            PlayerPart::Body => 0.3,
+            PlayerPart::Feet => 0.31,
+            PlayerPart::Pants => 0.32,
+            PlayerPart::Top => 0.33,
+            PlayerPart::Cloak => 0.34,
+            PlayerPart::Hair => 0.35,
+            PlayerPart::Head => 0.36,
+            PlayerPart::LeftHand => 0.37,
+            PlayerPart::RightHand => 0.38,
+        }
+    }
+}
+
 #[derive(
     Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Component,
 )]
@@ -221,6 +377,10 @@ pub struct Player;
 )]
 pub struct MainCamera;
 
+/// World sprites that stay at the same place on screen when the camera moves
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct ScreenAnchored;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Resource)]
 pub struct Journal {
     pub quests: HashMap<String, Quest>,
@@ -310,6 +470,12 @@ pub struct Item {
     pub position: SpritePosition,
     //pub dimension: SpriteDimension,
     pub consumable: bool,
+    // what the player sees when examining the item
+    #[serde(default)]
+    pub details: String,
+    // whether the item can be used from the inventory
+    #[serde(default)]
+    pub usable: bool,
 }
 
 impl Item {
@@ -327,9 +493,21 @@ impl Item {
             position: SpritePosition::new(x1, y1),
             //dimension: SpriteDimension::new(SpritePosition::new(x1, y1), SpritePosition::new(x1, y1)),
             consumable: false,
+            details: String::new(),
+            usable: false,
         }
     }
 
+    pub fn with_details<S: Into<String>>(mut self, details: S) -> Self {
+        self.details = details.into();
+        self
+    }
+
+    pub fn usable(mut self) -> Self {
+        self.usable = true;
+        self
+    }
+
     pub fn new_consumable<S1: Into<String>, S2: Into<String>, S3: Into<String>>(
         name: S1,
         description: S2,
@@ -346,6 +524,9 @@ impl Item {
 #[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
 pub struct Inventory {
     pub items: Vec<Item>,
+    // the name of the item in each part of the body
+    #[serde(default)]
+    pub equipped: BTreeMap<PlayerPart, String>,
 }
 
 impl Inventory {
@@ -368,15 +549,165 @@ impl Inventory {
         {
             self.items.remove(ix);
         }
+        self.equipped.retain(|_, name| name != item);
+        self
+    }
+
+    /// Equip an item of the inventory in the given part, moving it from the part it was in
+    pub fn equip(&mut self, item: &str, part: PlayerPart) -> &mut Self {
+        if self.contains_item(item) {
+            self.equipped.retain(|_, name| name != item);
+            self.equipped.insert(part, item.to_string());
+        }
         self
     }
+
+    pub fn unequip(&mut self, part: &PlayerPart) -> &mut Self {
+        self.equipped.remove(part);
+        self
+    }
+
+    pub fn equipped_in(&self, part: &PlayerPart) -> Option<&str> {
+        self.equipped.get(part).map(|s| s.as_str())
+    }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
+// the experience needed to go from level 0 to 1: each level then needs that much more
+pub const EXPERIENCE_STEP: u32 = 3;
+
+// the talents the game scripts rely on, the others only come from anthea.talents.ron
+pub const ANIMALS: &str = "animals";
+pub const PEOPLE: &str = "people";
+pub const WEAPONS: &str = "weapons";
+
+/// The points of the player in each talent, by code
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Resource)]
 pub struct Talents {
-    pub animals: u32,
-    pub people: u32,
-    pub weapons: u32,
+    pub values: BTreeMap<String, u32>,
+    // experience gained towards the next level of each talent
+    pub experience: BTreeMap<String, u32>,
+}
+
+/// The talents of the saves made before they were defined in data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum LegacyTalent {
+    Animals,
+    People,
+    Weapons,
+}
+
+impl LegacyTalent {
+    fn code(self) -> &'static str {
+        match self {
+            LegacyTalent::Animals => ANIMALS,
+            LegacyTalent::People => PEOPLE,
+            LegacyTalent::Weapons => WEAPONS,
+        }
+    }
+}
+
+/// The fields of the talents in a save, old or new
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum TalentsField {
+    Values,
+    Experience,
+    // old saves had one field per talent
+    Animals,
+    People,
+    Weapons,
+    #[serde(other)]
+    Other,
+}
+
+struct TalentsVisitor;
+
+impl<'de> Visitor<'de> for TalentsVisitor {
+    type Value = Talents;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("talents")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Talents, A::Error> {
+        let mut talents = Talents::default();
+        let mut legacy = false;
+        while let Some(field) = map.next_key::<TalentsField>()? {
+            match field {
+                TalentsField::Values => talents.values = map.next_value()?,
+                TalentsField::Experience if legacy => {
+                    talents.experience = map
+                        .next_value::<BTreeMap<LegacyTalent, u32>>()?
+                        .into_iter()
+                        .map(|(t, xp)| (t.code().to_owned(), xp))
+                        .collect();
+                }
+                TalentsField::Experience => talents.experience = map.next_value()?,
+                TalentsField::Animals => {
+                    legacy = true;
+                    talents.set(ANIMALS, map.next_value()?);
+                }
+                TalentsField::People => {
+                    legacy = true;
+                    talents.set(PEOPLE, map.next_value()?);
+                }
+                TalentsField::Weapons => {
+                    legacy = true;
+                    talents.set(WEAPONS, map.next_value()?);
+                }
+                TalentsField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(talents)
+    }
+}
+
+/// Talents load from saves made before they were defined in data too
+impl<'de> Deserialize<'de> for Talents {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Talents", &["values", "experience"], TalentsVisitor)
+    }
+}
+
+impl Talents {
+    pub fn value(&self, talent: &str) -> u32 {
+        self.values.get(talent).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, talent: &str, value: u32) -> &mut Self {
+        self.values.insert(talent.to_owned(), value);
+        self
+    }
+
+    pub fn raise(&mut self, talent: &str, points: u32) -> &mut Self {
+        *self.values.entry(talent.to_owned()).or_default() += points;
+        self
+    }
+
+    /// The points in all the talents
+    pub fn total(&self) -> u32 {
+        self.values.values().sum()
+    }
+
+    /// The experience needed to reach the next level of a talent
+    pub fn next_level(&self, talent: &str) -> u32 {
+        (self.value(talent) + 1) * EXPERIENCE_STEP
+    }
+
+    /// Gain experience in a talent, returning how many levels were gained
+    pub fn gain_experience(&mut self, talent: &str, points: u32) -> u32 {
+        let mut xp = self.experience.get(talent).copied().unwrap_or_default() + points;
+        let mut levels = 0;
+        while xp >= self.next_level(talent) {
+            xp -= self.next_level(talent);
+            self.raise(talent, 1);
+            levels += 1;
+        }
+        self.experience.insert(talent.to_owned(), xp);
+        levels
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
@@ -408,10 +739,27 @@ impl QuestFlags {
     }
 }
 
+/// What a spell is cast on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpellTarget {
+    // the player
+    #[default]
+    Player,
+    // a tile in sight, clicked on after choosing the spell
+    Tile,
+    // a character next to the player
+    Character,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Spell {
     pub name: String,
     pub description: String,
+    #[serde(default)]
+    pub target: SpellTarget,
+    // how long before the spell can be cast again, in milliseconds
+    #[serde(default)]
+    pub cooldown: u64,
 }
 
 impl Spell {
@@ -419,13 +767,28 @@ impl Spell {
         Self {
             name: name.into(),
             description: description.into(),
+            target: SpellTarget::Player,
+            cooldown: 0,
         }
     }
+
+    pub fn with_target(mut self, target: SpellTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: u64) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
 pub struct Spells {
     pub spells: Vec<Spell>,
+    // the time left before each spell can be cast again, in milliseconds
+    #[serde(default)]
+    pub cooldowns: HashMap<String, u64>,
 }
 
 impl Spells {
@@ -439,6 +802,40 @@ impl Spells {
         self.spells.iter().any(|i| i.name == spell)
     }
 
+    pub fn spell(&self, spell: &str) -> Option<&Spell> {
+        self.spells.iter().find(|i| i.name == spell)
+    }
+
+    /// Use the current definition of a spell if it is known, saves keep the one it was learnt with
+    pub fn update_spell(&mut self, spell: Spell) -> &mut Self {
+        if let Some(s) = self.spells.iter_mut().find(|s| s.name == spell.name) {
+            *s = spell;
+        }
+        self
+    }
+
+    /// The time left before a spell can be cast again, in milliseconds
+    pub fn cooldown(&self, spell: &str) -> u64 {
+        self.cooldowns.get(spell).copied().unwrap_or_default()
+    }
+
+    /// Start waiting before the spell can be cast again
+    pub fn start_cooldown(&mut self, spell: &str) -> &mut Self {
+        if let Some(cooldown) = self.spell(spell).map(|s| s.cooldown).filter(|c| *c > 0) {
+            self.cooldowns.insert(spell.to_owned(), cooldown);
+        }
+        self
+    }
+
+    /// Let some time pass for all the spells waiting to be cast again
+    pub fn tick(&mut self, delta: u64) -> &mut Self {
+        self.cooldowns.retain(|_, left| {
+            *left = left.saturating_sub(delta);
+            *left > 0
+        });
+        self
+    }
+
     pub fn remove_spell(&mut self, spell: &str) -> &mut Self {
         if let Some((ix, _e)) = self
             .spells
@@ -452,10 +849,13 @@ impl Spells {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BodyChangeEvent {
     pub part: PlayerPart,
     pub sprite: String,
+    // the tint of the sprite, if it changes
+    #[serde(default)]
+    pub color: Option<(f32, f32, f32)>,
 }
 
 impl BodyChangeEvent {
@@ -463,8 +863,14 @@ impl BodyChangeEvent {
         Self {
             part,
             sprite: sprite.into(),
+            color: None,
         }
     }
+
+    pub fn with_color(mut self, color: (f32, f32, f32)) -> Self {
+        self.color = Some(color);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, Component)]
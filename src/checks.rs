@@ -0,0 +1,133 @@
+use crate::base::*;
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// the chance of success when the talent matches the difficulty
+const BASE_CHANCE: i32 = 50;
+// how much each point of talent above or below the difficulty changes the chance
+const POINT_CHANCE: i32 = 20;
+// nothing is ever certain
+const MIN_CHANCE: i32 = 5;
+const MAX_CHANCE: i32 = 95;
+
+// the environment variable giving the seed of a new game, to play it again the same way
+pub const SEED_VARIABLE: &str = "ANTHEA_SEED";
+
+/// The random numbers of the game: seed it to get the same rolls every time
+#[derive(Debug, Clone, Resource)]
+pub struct GameRng {
+    // the seed the current rolls come from
+    pub seed: u64,
+    pub rng: StdRng,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        let seed = std::env::var(SEED_VARIABLE)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(rand::random);
+        GameRng::seeded(seed)
+    }
+}
+
+impl GameRng {
+    pub fn seeded(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Go on from a new seed drawn from the current rolls, so that a saved game
+    /// gets the same rolls after loading as when playing on
+    pub fn reseed(&mut self) -> u64 {
+        *self = GameRng::seeded(self.rng.gen());
+        self.seed
+    }
+}
+
+/// A roll of a talent against a difficulty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TalentCheck {
+    pub talent: String,
+    pub difficulty: u32,
+}
+
+impl TalentCheck {
+    pub fn new(talent: &str, difficulty: u32) -> Self {
+        Self {
+            talent: talent.to_owned(),
+            difficulty,
+        }
+    }
+
+    /// The chance of success, in percent
+    pub fn chance(&self, talents: &Talents) -> u32 {
+        let diff = talents.value(&self.talent) as i32 - self.difficulty as i32;
+        (BASE_CHANCE + diff * POINT_CHANCE).clamp(MIN_CHANCE, MAX_CHANCE) as u32
+    }
+
+    pub fn roll(&self, talents: &Talents, rng: &mut GameRng) -> bool {
+        rng.rng.gen_range(0..100) < self.chance(talents)
+    }
+
+    /// The text of a menu item, showing the odds
+    pub fn label(&self, text: &str, talents: &Talents) -> String {
+        format!("{} ({}%)", text, self.chance(talents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chance() {
+        let check = TalentCheck::new(WEAPONS, 2);
+        let mut talents = Talents::default();
+        assert_eq!(10, check.chance(&talents));
+        talents.set(WEAPONS, 2);
+        assert_eq!(50, check.chance(&talents));
+        talents.set(WEAPONS, 3);
+        assert_eq!(70, check.chance(&talents));
+        assert_eq!("Fight (70%)", check.label("Fight", &talents));
+        talents.set(WEAPONS, 10);
+        assert_eq!(MAX_CHANCE as u32, check.chance(&talents));
+        assert_eq!(
+            MIN_CHANCE as u32,
+            TalentCheck::new(PEOPLE, 10).chance(&talents)
+        );
+    }
+
+    #[test]
+    fn test_roll() {
+        let check = TalentCheck::new(ANIMALS, 0);
+        let talents = Talents::default();
+        let rolls = |seed| {
+            let mut rng = GameRng::seeded(seed);
+            (0..20)
+                .map(|_| check.roll(&talents, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        // the same seed gives the same rolls
+        assert_eq!(rolls(42), rolls(42));
+        let successes = rolls(7).into_iter().filter(|b| *b).count();
+        assert!(successes > 0 && successes < 20);
+    }
+
+    #[test]
+    fn test_reseed() {
+        let check = TalentCheck::new(ANIMALS, 0);
+        let talents = Talents::default();
+        let mut rng = GameRng::seeded(42);
+        let seed = rng.reseed();
+        assert_eq!(seed, rng.seed);
+        let played: Vec<bool> = (0..20).map(|_| check.roll(&talents, &mut rng)).collect();
+        // loading a game saved with that seed
+        let mut loaded = GameRng::seeded(seed);
+        let replayed: Vec<bool> = (0..20).map(|_| check.roll(&talents, &mut loaded)).collect();
+        assert_eq!(played, replayed);
+        assert_eq!(seed, GameRng::seeded(42).reseed());
+    }
+}
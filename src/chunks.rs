@@ -0,0 +1,306 @@
+use crate::base::*;
+use crate::fov::FieldOfView;
+use crate::setup::tile_color;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+pub const CHUNK_SIZE: i32 = 8;
+
+// chunks spawned on each side of the chunk the player is in: enough to cover the screen
+pub const CHUNK_RADIUS: i32 = 2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ChunkPosition {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn from_position(pos: &SpritePosition) -> Self {
+        Self::new(pos.x.div_euclid(CHUNK_SIZE), pos.y.div_euclid(CHUNK_SIZE))
+    }
+
+    pub fn around(&self) -> HashSet<ChunkPosition> {
+        let mut s = HashSet::new();
+        for x in self.x - CHUNK_RADIUS..=self.x + CHUNK_RADIUS {
+            for y in self.y - CHUNK_RADIUS..=self.y + CHUNK_RADIUS {
+                s.insert(ChunkPosition::new(x, y));
+            }
+        }
+        s
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChunkTile {
+    pub position: SpritePosition,
+    pub layer: usize,
+    pub index: usize,
+}
+
+/// All the map tiles, grouped by chunk, and the tile entities of the chunks currently spawned
+#[derive(Debug, Default, Clone, Resource)]
+pub struct MapChunks {
+    pub atlas: Handle<TextureAtlas>,
+    pub tiles: HashMap<ChunkPosition, Vec<ChunkTile>>,
+    pub loaded: HashMap<ChunkPosition, Vec<Entity>>,
+    pub center: Option<ChunkPosition>,
+}
+
+impl MapChunks {
+    pub fn new(atlas: Handle<TextureAtlas>) -> Self {
+        Self {
+            atlas,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_tile(&mut self, tile: ChunkTile) -> &mut Self {
+        self.tiles
+            .entry(ChunkPosition::from_position(&tile.position))
+            .or_default()
+            .push(tile);
+        self
+    }
+
+    pub fn remove_tile(&mut self, pos: &SpritePosition, layer: usize) -> &mut Self {
+        if let Some(tiles) = self.tiles.get_mut(&ChunkPosition::from_position(pos)) {
+            tiles.retain(|t| &t.position != pos || t.layer != layer);
+        }
+        self
+    }
+
+    pub fn forget_entities(&mut self, pos: &SpritePosition, entities: &[Entity]) -> &mut Self {
+        if let Some(loaded) = self.loaded.get_mut(&ChunkPosition::from_position(pos)) {
+            loaded.retain(|e| !entities.contains(e));
+        }
+        self
+    }
+
+    /// Chunks to spawn and chunks to despawn when the player stands on the given position
+    pub fn changes(&self, pos: &SpritePosition) -> (Vec<ChunkPosition>, Vec<ChunkPosition>) {
+        let center = ChunkPosition::from_position(pos);
+        if self.center == Some(center) {
+            return (vec![], vec![]);
+        }
+        let wanted = center.around();
+        let mut to_spawn: Vec<ChunkPosition> = wanted
+            .iter()
+            .filter(|c| !self.loaded.contains_key(c))
+            .copied()
+            .collect();
+        let mut to_despawn: Vec<ChunkPosition> = self
+            .loaded
+            .keys()
+            .filter(|c| !wanted.contains(c))
+            .copied()
+            .collect();
+        to_spawn.sort();
+        to_despawn.sort();
+        (to_spawn, to_despawn)
+    }
+}
+
+fn spawn_chunk(
+    commands: &mut Commands,
+    chunks: &mut MapChunks,
+    state: &mut AntheaState,
+    fov: &FieldOfView,
+    chunk: ChunkPosition,
+) -> usize {
+    let mut entities = vec![];
+    if let Some(tiles) = chunks.tiles.get(&chunk) {
+        for tile in tiles.iter() {
+            let visibility = if state.revealed.contains(&tile.position) {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+            let mut sprite = TextureAtlasSprite::new(tile.index);
+            sprite.color = tile_color(&tile.position, fov);
+            let ec = commands
+                .spawn(SpriteSheetBundle {
+                    sprite,
+                    texture_atlas: chunks.atlas.clone(),
+                    transform: Transform::from_translation(tile.position.to_vec3()),
+                    visibility,
+                    ..Default::default()
+                })
+                .insert(MapTile(tile.layer))
+                .id();
+            state
+                .positions
+                .entry(tile.position.clone())
+                .or_default()
+                .entities
+                .push(ec);
+            entities.push(ec);
+        }
+    }
+    let count = entities.len();
+    chunks.loaded.insert(chunk, entities);
+    count
+}
+
+fn despawn_chunk(
+    commands: &mut Commands,
+    chunks: &mut MapChunks,
+    state: &mut AntheaState,
+    chunk: ChunkPosition,
+) -> usize {
+    let mut count = 0;
+    if let Some(entities) = chunks.loaded.remove(&chunk) {
+        for e in entities.iter() {
+            commands.entity(*e).despawn_recursive();
+        }
+        if let Some(tiles) = chunks.tiles.get(&chunk) {
+            for tile in tiles.iter() {
+                if let Some(tes) = state.positions.get_mut(&tile.position) {
+                    tes.entities.retain(|e| !entities.contains(e));
+                }
+            }
+        }
+        count = entities.len();
+    }
+    count
+}
+
+/// Spawn the chunks around the player and despawn the ones that went out of range,
+/// returning the number of tiles spawned or despawned
+pub fn update_chunks(
+    commands: &mut Commands,
+    chunks: &mut MapChunks,
+    state: &mut AntheaState,
+    fov: &FieldOfView,
+) -> usize {
+    let (to_spawn, to_despawn) = chunks.changes(&state.map_position);
+    let mut count = 0;
+    for chunk in to_despawn.into_iter() {
+        count += despawn_chunk(commands, chunks, state, chunk);
+    }
+    for chunk in to_spawn.into_iter() {
+        count += spawn_chunk(commands, chunks, state, fov, chunk);
+    }
+    chunks.center = Some(ChunkPosition::from_position(&state.map_position));
+    count
+}
+
+pub fn chunk_system(
+    mut commands: Commands,
+    mut chunks: ResMut<MapChunks>,
+    mut state: ResMut<AntheaState>,
+    fov: Res<FieldOfView>,
+) {
+    if chunks.center != Some(ChunkPosition::from_position(&state.map_position)) {
+        update_chunks(&mut commands, &mut chunks, &mut state, &fov);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::reveal;
+    use bevy::ecs::system::CommandQueue;
+    use std::time::Instant;
+
+    fn synthetic_map(size: i32) -> (MapChunks, AntheaState) {
+        let mut chunks = MapChunks::default();
+        let mut state = AntheaState::default();
+        for x in 0..size {
+            for y in 0..size {
+                let pos = SpritePosition::new(x, y);
+                chunks.add_tile(ChunkTile {
+                    position: pos.clone(),
+                    layer: 0,
+                    index: 0,
+                });
+                let tes = state.positions.entry(pos).or_default();
+                // a wall every few tiles so that the line of sight has some work to do
+                if x % 7 == 0 && y % 5 == 0 {
+                    tes.passable = false;
+                    tes.transparent = false;
+                }
+            }
+        }
+        (chunks, state)
+    }
+
+    // walks the same path on the map and returns the work done per step and the time taken
+    fn walk(size: i32, steps: i32) -> (Vec<usize>, u128) {
+        let (mut chunks, mut state) = synthetic_map(size);
+        let mut world = World::new();
+        state.map_position = SpritePosition::new(40, 40);
+        let mut queue = CommandQueue::default();
+        {
+            let mut commands = Commands::new(&mut queue, &world);
+            update_chunks(
+                &mut commands,
+                &mut chunks,
+                &mut state,
+                &FieldOfView::default(),
+            );
+        }
+        queue.apply(&mut world);
+
+        let mut work = vec![];
+        let start = Instant::now();
+        for _ in 0..steps {
+            state.map_position.x += 1;
+            let mut queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, &world);
+            let mut fov = FieldOfView::default();
+            fov.compute(&state, VISIBILITY_DISTANCE);
+            let count = update_chunks(&mut commands, &mut chunks, &mut state, &fov)
+                + reveal(&mut state, &fov).len();
+            queue.apply(&mut world);
+            work.push(count);
+        }
+        (work, start.elapsed().as_micros())
+    }
+
+    #[test]
+    fn test_chunk_position() {
+        assert_eq!(
+            ChunkPosition::new(0, 0),
+            ChunkPosition::from_position(&SpritePosition::new(7, 7))
+        );
+        assert_eq!(
+            ChunkPosition::new(1, 0),
+            ChunkPosition::from_position(&SpritePosition::new(8, 0))
+        );
+        assert_eq!(
+            ChunkPosition::new(-1, -1),
+            ChunkPosition::from_position(&SpritePosition::new(-1, -8))
+        );
+    }
+
+    #[test]
+    fn test_remove_tile() {
+        let (mut chunks, _state) = synthetic_map(16);
+        let pos = SpritePosition::new(9, 3);
+        chunks.remove_tile(&pos, 0);
+        let tiles = chunks.tiles.get(&ChunkPosition::new(1, 0)).unwrap();
+        assert_eq!((CHUNK_SIZE * CHUNK_SIZE - 1) as usize, tiles.len());
+        assert!(!tiles.iter().any(|t| t.position == pos));
+    }
+
+    // run with --nocapture to see the timings
+    #[test]
+    fn test_step_cost_is_flat() {
+        let (small, small_time) = walk(128, 64);
+        let (large, large_time) = walk(1024, 64);
+        println!(
+            "64 steps on 128x128: {}us, on 1024x1024: {}us",
+            small_time, large_time
+        );
+        assert_eq!(small, large);
+        // at worst a row of chunks is despawned and another spawned, plus the revealed tiles
+        let max = (CHUNK_RADIUS * 2 + 1) * CHUNK_SIZE * CHUNK_SIZE * 2
+            + (VISIBILITY_DISTANCE * 2 + 1) * (VISIBILITY_DISTANCE * 2 + 1);
+        assert!(large.iter().all(|c| *c <= max as usize));
+    }
+}
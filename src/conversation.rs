@@ -0,0 +1,494 @@
+use crate::base::*;
+use crate::experience::*;
+use crate::menu::*;
+use crate::ui::*;
+use crate::world::*;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// the start of the code of the menus showing a conversation
+pub const CONVERSATION: &str = "conversation";
+
+pub struct ConversationPlugin;
+
+impl Plugin for ConversationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Conversations>()
+            .init_resource::<PendingResponses>()
+            .add_event::<ConversationEvent>()
+            .add_system(start_conversation.in_set(OnUpdate(GameState::Running)))
+            .add_systems(
+                (
+                    conversation_system,
+                    // the click showing the whole page must not show the responses too
+                    show_responses.before(advance_dialogue),
+                )
+                    .in_set(OnUpdate(GameState::Menu)),
+            )
+            .add_system(forget_responses.in_schedule(OnExit(GameState::Menu)));
+    }
+}
+
+/// What must be true for a node to open a conversation, or for a response to be offered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Flag(String, String),
+    NoFlag(String, String),
+    // at least that many points in a talent
+    Talent(String, u32),
+    Item(String),
+}
+
+/// What happens when a node is shown or a response chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    SetFlag(String, String),
+    UnsetFlag(String, String),
+    // raise a talent by that many points
+    Talent(String, u32),
+    Quest(String, String),
+    Journal(String, String),
+    RemoveItem(String),
+    // anything else, for the stage to handle via a ConversationEvent
+    Trigger(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub text: String,
+    // the node to go to, or the end of the conversation
+    pub next: Option<String>,
+    pub requirements: Vec<Requirement>,
+    pub effects: Vec<Effect>,
+}
+
+impl Response {
+    pub fn new<S: Into<String>>(text: S, next: Option<&str>) -> Self {
+        Self {
+            text: text.into(),
+            next: next.map(|n| n.to_owned()),
+            requirements: vec![],
+            effects: vec![],
+        }
+    }
+
+    pub fn with_requirement(mut self, requirement: Requirement) -> Self {
+        self.requirements.push(requirement);
+        self
+    }
+
+    pub fn with_effect(mut self, effect: Effect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationNode {
+    pub text: String,
+    pub effects: Vec<Effect>,
+    pub responses: Vec<Response>,
+}
+
+impl ConversationNode {
+    pub fn new<S: Into<String>>(text: S, responses: Vec<Response>) -> Self {
+        Self {
+            text: text.into(),
+            effects: vec![],
+            responses,
+        }
+    }
+
+    pub fn with_effect(mut self, effect: Effect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+}
+
+/// What a character can say: the conversation starts with the first opening whose requirements hold
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Conversation {
+    pub title: String,
+    pub openings: Vec<(Vec<Requirement>, String)>,
+    pub nodes: HashMap<String, ConversationNode>,
+}
+
+impl Conversation {
+    pub fn new<S: Into<String>>(title: S) -> Self {
+        Self {
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_node<S: Into<String>>(&mut self, id: S, node: ConversationNode) -> &mut Self {
+        self.nodes.insert(id.into(), node);
+        self
+    }
+
+    pub fn add_opening<S: Into<String>>(
+        &mut self,
+        requirements: Vec<Requirement>,
+        id: S,
+    ) -> &mut Self {
+        self.openings.push((requirements, id.into()));
+        self
+    }
+
+    pub fn opening(&self, state: &ConversationState) -> Option<&String> {
+        self.openings
+            .iter()
+            .find(|(requirements, _)| state.check_all(requirements))
+            .map(|(_, id)| id)
+    }
+}
+
+/// The conversations of the current stage, by character name
+#[derive(Debug, Default, Clone, PartialEq, Eq, Resource)]
+pub struct Conversations(pub HashMap<String, Conversation>);
+
+/// A Trigger effect happened in a conversation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationEvent {
+    pub character: String,
+    pub code: String,
+}
+
+/// The responses to show once the character has finished talking
+#[derive(Debug, Default, Clone, Resource)]
+pub struct PendingResponses(pub Option<Menu>);
+
+/// A line said by a character, shown with their portrait
+pub fn say(area: &Area, name: &str, text: &str) -> MessageEvent {
+    let event = MessageEvent::new(text, MessageStyle::Info);
+    match area.character_from_name(name) {
+        Some(chr) => event.with_speaker(&chr.name, &chr.sprite),
+        None => event,
+    }
+}
+
+/// The parts of the game a conversation looks at and changes
+pub struct ConversationState<'a> {
+    pub flags: &'a mut QuestFlags,
+    pub talents: &'a mut Talents,
+    pub inventory: &'a mut Inventory,
+    pub journal: &'a mut Journal,
+    // what to send once the effects are applied
+    pub journal_events: Vec<JournalEvent>,
+    pub talent_events: Vec<TalentEvent>,
+    pub triggers: Vec<String>,
+}
+
+impl<'a> ConversationState<'a> {
+    pub fn new(
+        flags: &'a mut QuestFlags,
+        talents: &'a mut Talents,
+        inventory: &'a mut Inventory,
+        journal: &'a mut Journal,
+    ) -> Self {
+        Self {
+            flags,
+            talents,
+            inventory,
+            journal,
+            journal_events: vec![],
+            talent_events: vec![],
+            triggers: vec![],
+        }
+    }
+
+    pub fn check(&self, requirement: &Requirement) -> bool {
+        match requirement {
+            Requirement::Flag(quest, flag) => self.flags.has_flag(quest.as_str(), flag.as_str()),
+            Requirement::NoFlag(quest, flag) => !self.flags.has_flag(quest.as_str(), flag.as_str()),
+            Requirement::Talent(talent, points) => self.talents.value(talent) >= *points,
+            Requirement::Item(name) => self.inventory.contains_item(name),
+        }
+    }
+
+    pub fn check_all(&self, requirements: &[Requirement]) -> bool {
+        requirements.iter().all(|c| self.check(c))
+    }
+
+    pub fn apply(&mut self, effects: &[Effect]) {
+        for effect in effects.iter() {
+            match effect {
+                Effect::SetFlag(quest, flag) => {
+                    self.flags.set_flag(quest.as_str(), flag.as_str());
+                }
+                Effect::UnsetFlag(quest, flag) => {
+                    self.flags.unset_flag(quest.as_str(), flag.as_str());
+                }
+                Effect::Talent(talent, points) => self
+                    .talent_events
+                    .push(TalentEvent::Raise(talent.clone(), *points)),
+                Effect::Quest(code, text) => {
+                    self.journal.add_quest(Quest::new(code, text));
+                }
+                Effect::Journal(quest, text) => {
+                    self.journal_events.push(JournalEvent::new(quest, text))
+                }
+                Effect::RemoveItem(name) => {
+                    self.inventory.remove_item(name);
+                }
+                Effect::Trigger(code) => self.triggers.push(code.clone()),
+            }
+        }
+    }
+}
+
+fn menu_code(character: &str, id: &str) -> String {
+    format!("{}/{}/{}", CONVERSATION, character, id)
+}
+
+/// The character and node shown by a menu, if it is part of a conversation
+fn parse_menu_code(code: &str) -> Option<(&str, &str)> {
+    let mut parts = code.splitn(3, '/');
+    if parts.next() != Some(CONVERSATION) {
+        return None;
+    }
+    Some((parts.next()?, parts.next()?))
+}
+
+/// The menu of the responses to a node, the ones that cannot be chosen left out,
+/// or nothing if the conversation ends there
+pub fn node_menu(
+    character: &str,
+    conversation: &Conversation,
+    id: &str,
+    state: &ConversationState,
+) -> Option<Menu> {
+    let node = conversation.nodes.get(id)?;
+    let items: Vec<MenuItem> = node
+        .responses
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| state.check_all(&r.requirements))
+        .map(|(i, r)| MenuItem::new(i.to_string(), &r.text))
+        .collect();
+    if items.is_empty() {
+        return None;
+    }
+    Some(Menu::new(
+        menu_code(character, id),
+        &conversation.title,
+        items,
+    ))
+}
+
+fn start_conversation(
+    area: Res<Area>,
+    conversations: Res<Conversations>,
+    mut event_reader: EventReader<CharacterEvent>,
+    mut flags: ResMut<QuestFlags>,
+    mut talents: ResMut<Talents>,
+    mut inventory: ResMut<Inventory>,
+    mut journal: ResMut<Journal>,
+    mut journal_events: EventWriter<JournalEvent>,
+    mut talent_events: EventWriter<TalentEvent>,
+    mut triggers: EventWriter<ConversationEvent>,
+    mut queue: EventWriter<MessageEvent>,
+    mut pending: ResMut<PendingResponses>,
+    mut menus: ResMut<Menus>,
+    mut appstate: ResMut<NextState<GameState>>,
+) {
+    for e in event_reader.iter() {
+        let Some(conversation) = conversations.0.get(&e.0) else {
+            continue;
+        };
+        let mut state =
+            ConversationState::new(&mut flags, &mut talents, &mut inventory, &mut journal);
+        let Some(id) = conversation.opening(&state) else {
+            continue;
+        };
+        if let Some(node) = conversation.nodes.get(id) {
+            state.apply(&node.effects);
+            queue.send(say(&area, &e.0, &node.text));
+        }
+        if let Some(m) = node_menu(&e.0, conversation, id, &state) {
+            menus.clear();
+            pending.0 = Some(m);
+            appstate.set(GameState::Menu);
+        }
+        journal_events.send_batch(state.journal_events);
+        talent_events.send_batch(state.talent_events);
+        triggers.send_batch(state.triggers.into_iter().map(|code| ConversationEvent {
+            character: e.0.clone(),
+            code,
+        }));
+    }
+}
+
+/// Follow the chosen response: the character says the next node, whose responses wait in
+/// `PendingResponses` for `show_responses` to push them on top of the previous menu
+fn conversation_system(
+    area: Res<Area>,
+    conversations: Res<Conversations>,
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut flags: ResMut<QuestFlags>,
+    mut talents: ResMut<Talents>,
+    mut inventory: ResMut<Inventory>,
+    mut journal: ResMut<Journal>,
+    mut journal_events: EventWriter<JournalEvent>,
+    mut talent_events: EventWriter<TalentEvent>,
+    mut triggers: EventWriter<ConversationEvent>,
+    mut close_menu: EventWriter<CloseMenuEvent>,
+    mut clearm: EventWriter<ClearMessage>,
+    mut pending: ResMut<PendingResponses>,
+    mut queue: EventWriter<MessageEvent>,
+) {
+    let Some((character, node, response)) = event_reader.iter().find_map(|e| {
+        let (character, id) = parse_menu_code(&e.menu)?;
+        let conversation = conversations.0.get(character)?;
+        let response = conversation
+            .nodes
+            .get(id)?
+            .responses
+            .get(e.item.parse::<usize>().ok()?)?;
+        Some((character.to_owned(), conversation, response))
+    }) else {
+        return;
+    };
+    let mut state = ConversationState::new(&mut flags, &mut talents, &mut inventory, &mut journal);
+    state.apply(&response.effects);
+    match response
+        .next
+        .as_ref()
+        .and_then(|id| node.nodes.get(id).map(|n| (id, n)))
+    {
+        Some((id, next)) => {
+            state.apply(&next.effects);
+            queue.send(say(&area, &character, &next.text));
+            pending.0 = node_menu(&character, node, id, &state);
+            if pending.0.is_none() {
+                // the last words stay on screen
+                close_menu.send(CloseMenuEvent);
+            }
+        }
+        None => {
+            close_menu.send(CloseMenuEvent);
+            clearm.send(ClearMessage);
+        }
+    }
+    journal_events.send_batch(state.journal_events);
+    talent_events.send_batch(state.talent_events);
+    triggers.send_batch(state.triggers.into_iter().map(|code| ConversationEvent {
+        character: character.clone(),
+        code,
+    }));
+}
+
+/// Once the character has finished talking, the next click or key shows the responses
+fn show_responses(
+    mouse_button_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    dialogue: Res<Dialogue>,
+    mut pending: ResMut<PendingResponses>,
+    menus: ResMut<Menus>,
+    queue: EventWriter<MessageEvent>,
+) {
+    if pending.0.is_none() || dialogue.is_waiting() {
+        return;
+    }
+    if mouse_button_input.just_pressed(MouseButton::Left)
+        || keyboard_input.any_just_pressed([KeyCode::Space, KeyCode::Return])
+    {
+        if let Some(m) = pending.0.take() {
+            push_menu(queue, menus, m);
+        }
+    }
+}
+
+/// Leaving the conversation before the responses are shown
+fn forget_responses(mut pending: ResMut<PendingResponses>) {
+    pending.0 = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUEST: &str = "quest";
+
+    fn conversation() -> Conversation {
+        let mut c = Conversation::new("Someone");
+        c.add_opening(
+            vec![Requirement::Flag(QUEST.into(), QUEST_STARTED.into())],
+            "again",
+        )
+        .add_opening(vec![], "hello")
+        .add_node(
+            "hello",
+            ConversationNode::new(
+                "Hello!",
+                vec![
+                    Response::new("Can I help?", Some("help"))
+                        .with_effect(Effect::SetFlag(QUEST.into(), QUEST_STARTED.into())),
+                    Response::new("You look tired.", None)
+                        .with_requirement(Requirement::Talent(PEOPLE.into(), 2)),
+                    Response::new("Bye.", None),
+                ],
+            ),
+        )
+        .add_node(
+            "help",
+            ConversationNode::new("Sure!", vec![])
+                .with_effect(Effect::Journal(QUEST.into(), "I help".into()))
+                .with_effect(Effect::Trigger("helped".into())),
+        );
+        c
+    }
+
+    #[test]
+    fn test_openings() {
+        let c = conversation();
+        let (mut flags, mut talents, mut inventory, mut journal) = Default::default();
+        let mut state =
+            ConversationState::new(&mut flags, &mut talents, &mut inventory, &mut journal);
+        assert_eq!(Some(&"hello".to_string()), c.opening(&state));
+        state.apply(&c.nodes["hello"].responses[0].effects);
+        assert_eq!(Some(&"again".to_string()), c.opening(&state));
+    }
+
+    #[test]
+    fn test_node_menu() {
+        let c = conversation();
+        let (mut flags, mut talents, mut inventory, mut journal) = Default::default();
+        let state = ConversationState::new(&mut flags, &mut talents, &mut inventory, &mut journal);
+        let m = node_menu("someone", &c, "hello", &state).unwrap();
+        assert_eq!(2, m.items().len());
+        // the talented response is there once the player is good enough with people
+        state.talents.set(PEOPLE, 2);
+        let m = node_menu("someone", &c, "hello", &state).unwrap();
+        assert_eq!(3, m.items().len());
+        // nothing to answer, the conversation ends
+        assert!(node_menu("someone", &c, "help", &state).is_none());
+        assert_eq!(Some(("someone", "hello")), parse_menu_code(m.code()));
+        assert!(node_menu("someone", &c, "nowhere", &state).is_none());
+        assert_eq!(None, parse_menu_code(JOURNAL));
+    }
+
+    #[test]
+    fn test_effects() {
+        let c = conversation();
+        let (mut flags, mut talents, mut inventory, mut journal) = Default::default();
+        let mut state =
+            ConversationState::new(&mut flags, &mut talents, &mut inventory, &mut journal);
+        state.apply(&c.nodes["help"].effects);
+        state.apply(&[
+            Effect::Quest(QUEST.into(), "A quest".into()),
+            Effect::Talent(PEOPLE.into(), 1),
+        ]);
+        assert_eq!(
+            vec![JournalEvent::new(QUEST, "I help")],
+            state.journal_events
+        );
+        assert_eq!(vec!["helped".to_string()], state.triggers);
+        assert_eq!(
+            vec![TalentEvent::Raise(PEOPLE.into(), 1)],
+            state.talent_events
+        );
+        assert_eq!(0, state.talents.value(PEOPLE));
+        assert!(state.journal.quests.contains_key(QUEST));
+    }
+}
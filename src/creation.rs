@@ -0,0 +1,314 @@
+use crate::appearance::Appearance;
+use crate::base::*;
+use crate::experience::TalentRegistry;
+use crate::ui::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const BODY: &str = "body";
+pub const HAIR: &str = "hair";
+pub const TOP: &str = "top";
+pub const PANTS: &str = "pants";
+pub const START: &str = "start";
+
+pub struct CreationPlugin;
+
+impl Plugin for CreationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CharacterChoices::default())
+            .add_system(show_creation.in_schedule(OnEnter(GameState::Creation)))
+            .add_system(creation_click_system.in_set(OnUpdate(GameState::Creation)));
+    }
+}
+
+/// What the player can pick from when creating the character, by name
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreationOptions {
+    // skin tints
+    pub bodies: Vec<(String, (f32, f32, f32))>,
+    // hair sprites
+    pub hair: Vec<(String, String)>,
+    // clothing tints
+    pub colours: Vec<(String, (f32, f32, f32))>,
+    // the talent points to share at the start
+    pub talent_points: u32,
+}
+
+impl CreationOptions {
+    pub fn points_left(&self, talents: &Talents) -> u32 {
+        self.talent_points.saturating_sub(talents.total())
+    }
+}
+
+/// What the player chose when creating the character
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Resource)]
+pub struct CharacterChoices {
+    pub body: String,
+    pub hair: String,
+    pub top: String,
+    pub pants: String,
+    pub talents: Talents,
+}
+
+impl CharacterChoices {
+    pub fn new(options: &CreationOptions) -> Self {
+        let first = |v: &[(String, (f32, f32, f32))]| v.first().map(|c| c.0.clone());
+        Self {
+            body: first(&options.bodies).unwrap_or_default(),
+            hair: options
+                .hair
+                .first()
+                .map(|h| h.0.clone())
+                .unwrap_or_default(),
+            top: first(&options.colours).unwrap_or_default(),
+            pants: first(&options.colours).unwrap_or_default(),
+            talents: Talents::default(),
+        }
+    }
+
+    /// Change a choice following the clicked menu item, talents use their code
+    pub fn pick(
+        &mut self,
+        options: &CreationOptions,
+        registry: &TalentRegistry,
+        code: &str,
+    ) -> &mut Self {
+        let left = options.points_left(&self.talents);
+        match code {
+            BODY => self.body = next_option(&options.bodies, &self.body),
+            HAIR => self.hair = next_option(&options.hair, &self.hair),
+            TOP => self.top = next_option(&options.colours, &self.top),
+            PANTS => self.pants = next_option(&options.colours, &self.pants),
+            _ if registry.get(code).is_some() => {
+                let value = add_point(self.talents.value(code), left);
+                self.talents.set(code, value);
+            }
+            _ => (),
+        }
+        self
+    }
+
+    /// The changes to the player parts that show the choices
+    pub fn body_changes(&self, appearance: &Appearance) -> Vec<BodyChangeEvent> {
+        let options = &appearance.creation;
+        let mut v = vec![];
+        let mut tinted = |part: PlayerPart, colours: &[(String, (f32, f32, f32))], name: &str| {
+            if let Some((_, color)) = colours.iter().find(|c| c.0 == name) {
+                let sprite = appearance.base_sprite(&part).to_string();
+                v.push(BodyChangeEvent::new(part, sprite).with_color(*color));
+            }
+        };
+        tinted(PlayerPart::Body, &options.bodies, &self.body);
+        tinted(PlayerPart::Top, &options.colours, &self.top);
+        tinted(PlayerPart::Pants, &options.colours, &self.pants);
+        if let Some((_, sprite)) = options.hair.iter().find(|h| h.0 == self.hair) {
+            v.push(BodyChangeEvent::new(PlayerPart::Hair, sprite));
+        }
+        v
+    }
+}
+
+/// The option after the current one, going back to the first one after the last
+fn next_option<T>(options: &[(String, T)], current: &str) -> String {
+    let idx = options
+        .iter()
+        .position(|o| o.0 == current)
+        .map(|i| (i + 1) % options.len())
+        .unwrap_or_default();
+    options.get(idx).map(|o| o.0.clone()).unwrap_or_default()
+}
+
+/// Add a point to a talent, or take all its points back when there are none left
+fn add_point(talent: u32, left: u32) -> u32 {
+    if left > 0 {
+        talent + 1
+    } else {
+        0
+    }
+}
+
+fn creation_messages(
+    options: &CreationOptions,
+    registry: &TalentRegistry,
+    choices: &CharacterChoices,
+) -> Vec<Message> {
+    let item =
+        |code: &str, text: String| Message::new(text, MessageStyle::Interaction(code.into()));
+    let mut msgs = vec![
+        Message::new("Who is Anthea?", MessageStyle::MenuTitle),
+        item(BODY, format!("Skin: {}", choices.body)),
+        item(HAIR, format!("Hair: {}", choices.hair)),
+        item(TOP, format!("Top: {}", choices.top)),
+        item(PANTS, format!("Pants: {}", choices.pants)),
+        Message::new(
+            "Talent points left:",
+            MessageStyle::Table(vec![format!(
+                "{:>3}",
+                options.points_left(&choices.talents)
+            )]),
+        ),
+    ];
+    msgs.extend(registry.talents.iter().map(|t| {
+        item(
+            &t.code,
+            format!("{}: {}", t.name, choices.talents.value(&t.code)),
+        )
+    }));
+    msgs.push(item(START, "Start the quest".into()));
+    msgs
+}
+
+fn show_creation(
+    handles: Res<AntheaHandles>,
+    appearances: Res<Assets<Appearance>>,
+    registries: Res<Assets<TalentRegistry>>,
+    mut choices: ResMut<CharacterChoices>,
+    mut queue: EventWriter<MessageEvent>,
+    mut body_change: EventWriter<BodyChangeEvent>,
+) {
+    let (Some(appearance), Some(registry)) = (
+        appearances.get(&handles.appearance_handle),
+        registries.get(&handles.talents_handle),
+    ) else {
+        return;
+    };
+    *choices = CharacterChoices::new(&appearance.creation);
+    queue.send(MessageEvent::new_multi(creation_messages(
+        &appearance.creation,
+        registry,
+        &choices,
+    )));
+    body_change.send_batch(choices.body_changes(appearance));
+}
+
+fn creation_click_system(
+    item_query: Query<(&Interaction, &InteractionItem), Changed<Interaction>>,
+    handles: Res<AntheaHandles>,
+    appearances: Res<Assets<Appearance>>,
+    registries: Res<Assets<TalentRegistry>>,
+    mut choices: ResMut<CharacterChoices>,
+    mut talents: ResMut<Talents>,
+    mut queue: EventWriter<MessageEvent>,
+    mut clearm: EventWriter<ClearMessage>,
+    mut body_change: EventWriter<BodyChangeEvent>,
+    mut appstate: ResMut<NextState<GameState>>,
+) {
+    let (Some(appearance), Some(registry)) = (
+        appearances.get(&handles.appearance_handle),
+        registries.get(&handles.talents_handle),
+    ) else {
+        return;
+    };
+    if let Some((_, item)) = item_query.iter().find(|(i, _)| **i == Interaction::Clicked) {
+        if item.0 == START || item.0 == CLOSE {
+            *talents = choices.talents.clone();
+            clearm.send(ClearMessage);
+            appstate.set(GameState::Running);
+            return;
+        }
+        let before = choices.body_changes(appearance);
+        choices.pick(&appearance.creation, registry, &item.0);
+        // only the parts that changed, to keep the saved body changes short
+        body_change.send_batch(
+            choices
+                .body_changes(appearance)
+                .into_iter()
+                .filter(|e| !before.contains(e)),
+        );
+        queue.send(MessageEvent::new_multi(creation_messages(
+            &appearance.creation,
+            registry,
+            &choices,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experience::TalentDefinition;
+
+    fn registry() -> TalentRegistry {
+        let talent = |code: &str| TalentDefinition {
+            code: code.into(),
+            name: code.into(),
+            description: String::new(),
+            icon: String::new(),
+        };
+        TalentRegistry {
+            talents: vec![talent(ANIMALS), talent(PEOPLE), talent(WEAPONS)],
+        }
+    }
+
+    fn options() -> CreationOptions {
+        CreationOptions {
+            bodies: vec![
+                ("Fair".into(), (1.0, 1.0, 1.0)),
+                ("Dark".into(), (0.5, 0.4, 0.3)),
+            ],
+            hair: vec![
+                ("Long".into(), "long.png".into()),
+                ("Short".into(), "short.png".into()),
+            ],
+            colours: vec![("White".into(), (1.0, 1.0, 1.0))],
+            talent_points: 2,
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let options = options();
+        let mut choices = CharacterChoices::new(&options);
+        assert_eq!("Fair", choices.body);
+        let registry = registry();
+        choices
+            .pick(&options, &registry, BODY)
+            .pick(&options, &registry, HAIR);
+        assert_eq!("Dark", choices.body);
+        assert_eq!("Short", choices.hair);
+        choices
+            .pick(&options, &registry, BODY)
+            .pick(&options, &registry, TOP);
+        assert_eq!("Fair", choices.body);
+        assert_eq!("White", choices.top);
+    }
+
+    #[test]
+    fn test_talent_points() {
+        let options = options();
+        let mut choices = CharacterChoices::new(&options);
+        let registry = registry();
+        choices
+            .pick(&options, &registry, ANIMALS)
+            .pick(&options, &registry, WEAPONS);
+        assert_eq!(0, options.points_left(&choices.talents));
+        // no points left, the talent gives its points back
+        choices.pick(&options, &registry, ANIMALS);
+        assert_eq!(0, choices.talents.value(ANIMALS));
+        assert_eq!(1, options.points_left(&choices.talents));
+        choices.pick(&options, &registry, PEOPLE);
+        assert_eq!(1, choices.talents.value(PEOPLE));
+        assert_eq!(1, choices.talents.value(WEAPONS));
+        // an unknown code is not a talent
+        choices.pick(&options, &registry, "magic");
+        assert_eq!(0, choices.talents.value("magic"));
+    }
+
+    #[test]
+    fn test_body_changes() {
+        let appearance = Appearance {
+            creation: options(),
+            ..Default::default()
+        };
+        let mut choices = CharacterChoices::new(&appearance.creation);
+        choices.pick(&appearance.creation, &registry(), BODY);
+        let changes = choices.body_changes(&appearance);
+        assert_eq!(4, changes.len());
+        assert_eq!(PlayerPart::Body, changes[0].part);
+        assert_eq!(Some((0.5, 0.4, 0.3)), changes[0].color);
+        assert_eq!(
+            BodyChangeEvent::new(PlayerPart::Hair, "long.png"),
+            changes[3]
+        );
+    }
+}
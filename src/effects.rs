@@ -0,0 +1,264 @@
+use crate::base::*;
+use crate::spells::SpellCastEvent;
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+// how long each effect lasts, in milliseconds
+pub const ILLUSION_DELAY: u64 = 1500;
+pub const FADE_DELAY: u64 = 800;
+pub const FLASH_DELAY: u64 = 300;
+pub const PARTICLES_DELAY: u64 = 600;
+// how many particles burst out at once
+const PARTICLE_COUNT: usize = 12;
+// how far the particles go, in pixels
+const PARTICLE_SPREAD: f32 = 24.0;
+const PARTICLE_SIZE: f32 = 4.0;
+// above the map, the characters and the player
+const EFFECT_Z: f32 = 0.9;
+
+pub const MAGIC_COLOR: Color = Color::rgb(0.7, 0.5, 1.0);
+pub const DUST_COLOR: Color = Color::rgb(0.6, 0.55, 0.45);
+// illusions look a bit ghostly
+const ILLUSION_COLOR: Color = Color::rgb(0.8, 0.85, 1.0);
+
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EffectEvent>()
+            // effects keep playing while menus and messages are shown
+            .add_systems((spell_effects, tile_effects, effect_system, animate_effects).chain());
+    }
+}
+
+/// A visual effect to show on the map
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectEvent {
+    // a sprite appearing for a while, then fading away
+    Illusion(SpritePosition, String),
+    // a short burst of light on a tile
+    Flash(SpritePosition),
+    // small squares bursting out of a tile
+    Particles(SpritePosition, Color),
+    // an entity fading away before being despawned
+    FadeOut(Entity),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectKind {
+    Illusion,
+    Flash,
+    Particle { origin: Vec3, direction: Vec2 },
+    FadeOut,
+}
+
+/// An effect playing, despawned with its entity once done
+#[derive(Debug, Clone, PartialEq, Component)]
+pub struct VisualEffect {
+    pub kind: EffectKind,
+    pub elapsed: u64,
+    pub duration: u64,
+}
+
+impl VisualEffect {
+    pub fn new(kind: EffectKind, duration: u64) -> Self {
+        Self {
+            kind,
+            elapsed: 0,
+            duration,
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.duration == 0 {
+            1.0
+        } else {
+            (self.elapsed as f32 / self.duration as f32).min(1.0)
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    /// How opaque the effect is now
+    pub fn alpha(&self) -> f32 {
+        let p = self.progress();
+        match self.kind {
+            // fade in quickly, stay, then fade out
+            EffectKind::Illusion => {
+                if p < 0.2 {
+                    p / 0.2
+                } else if p > 0.7 {
+                    (1.0 - p) / 0.3
+                } else {
+                    1.0
+                }
+            }
+            EffectKind::Flash => 0.8 * (1.0 - p),
+            EffectKind::Particle { .. } | EffectKind::FadeOut => 1.0 - p,
+        }
+    }
+
+    /// Where a particle is now, moving away from its origin
+    pub fn position(&self) -> Option<Vec3> {
+        match self.kind {
+            EffectKind::Particle { origin, direction } => {
+                Some(origin + (direction * PARTICLE_SPREAD * self.progress()).extend(0.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Every spell shows some magic where it lands
+fn spell_effects(
+    mut event_reader: EventReader<SpellCastEvent>,
+    mut effects: EventWriter<EffectEvent>,
+) {
+    for e in event_reader.iter() {
+        effects.send(EffectEvent::Particles(e.position.clone(), MAGIC_COLOR));
+    }
+}
+
+/// Tiles going away, like a gate opening, raise some dust
+fn tile_effects(
+    mut event_reader: EventReader<RemoveTileEvent>,
+    mut effects: EventWriter<EffectEvent>,
+) {
+    for e in event_reader.iter() {
+        effects.send(EffectEvent::Flash(e.position.clone()));
+        effects.send(EffectEvent::Particles(e.position.clone(), DUST_COLOR));
+    }
+}
+
+fn effect_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut event_reader: EventReader<EffectEvent>,
+) {
+    for e in event_reader.iter() {
+        match e {
+            EffectEvent::Illusion(pos, sprite) => {
+                commands
+                    .spawn(SpriteBundle {
+                        texture: asset_server.load(sprite.as_str()),
+                        sprite: Sprite {
+                            color: ILLUSION_COLOR.with_a(0.0),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation(pos.to_vec3_z(EFFECT_Z)),
+                        ..Default::default()
+                    })
+                    .insert(VisualEffect::new(EffectKind::Illusion, ILLUSION_DELAY));
+            }
+            EffectEvent::Flash(pos) => {
+                commands
+                    .spawn(SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::rgba(1.0, 1.0, 0.9, 0.8),
+                            custom_size: Some(Vec2::splat(SPRITE_SIZE as f32)),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation(pos.to_vec3_z(EFFECT_Z)),
+                        ..Default::default()
+                    })
+                    .insert(VisualEffect::new(EffectKind::Flash, FLASH_DELAY));
+            }
+            EffectEvent::Particles(pos, color) => {
+                let origin = pos.to_vec3_z(EFFECT_Z);
+                for i in 0..PARTICLE_COUNT {
+                    let direction = Vec2::from_angle(i as f32 * TAU / PARTICLE_COUNT as f32);
+                    commands
+                        .spawn(SpriteBundle {
+                            sprite: Sprite {
+                                color: *color,
+                                custom_size: Some(Vec2::splat(PARTICLE_SIZE)),
+                                ..Default::default()
+                            },
+                            transform: Transform::from_translation(origin),
+                            ..Default::default()
+                        })
+                        .insert(VisualEffect::new(
+                            EffectKind::Particle { origin, direction },
+                            PARTICLES_DELAY,
+                        ));
+                }
+            }
+            EffectEvent::FadeOut(entity) => {
+                if let Some(mut ec) = commands.get_entity(*entity) {
+                    ec.insert(VisualEffect::new(EffectKind::FadeOut, FADE_DELAY));
+                }
+            }
+        }
+    }
+}
+
+fn animate_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effect_query: Query<(
+        Entity,
+        &mut VisualEffect,
+        &mut Transform,
+        Option<&mut Sprite>,
+        Option<&mut TextureAtlasSprite>,
+    )>,
+) {
+    let delta = time.delta().as_millis() as u64;
+    for (e, mut effect, mut transform, sprite, atlas_sprite) in effect_query.iter_mut() {
+        effect.elapsed += delta;
+        if effect.is_done() {
+            commands.entity(e).despawn_recursive();
+            continue;
+        }
+        let alpha = effect.alpha();
+        if let Some(mut sprite) = sprite {
+            sprite.color.set_a(alpha);
+        }
+        if let Some(mut sprite) = atlas_sprite {
+            sprite.color.set_a(alpha);
+        }
+        if let Some(pos) = effect.position() {
+            transform.translation = pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpha() {
+        let mut illusion = VisualEffect::new(EffectKind::Illusion, 1000);
+        assert_eq!(0.0, illusion.alpha());
+        illusion.elapsed = 500;
+        assert_eq!(1.0, illusion.alpha());
+        illusion.elapsed = 1000;
+        assert_eq!(0.0, illusion.alpha());
+        assert!(illusion.is_done());
+        let mut fade = VisualEffect::new(EffectKind::FadeOut, 800);
+        fade.elapsed = 200;
+        assert_eq!(0.75, fade.alpha());
+        assert!(!fade.is_done());
+    }
+
+    #[test]
+    fn test_particle() {
+        let mut particle = VisualEffect::new(
+            EffectKind::Particle {
+                origin: Vec3::new(32.0, 0.0, EFFECT_Z),
+                direction: Vec2::X,
+            },
+            PARTICLES_DELAY,
+        );
+        assert_eq!(Some(Vec3::new(32.0, 0.0, EFFECT_Z)), particle.position());
+        particle.elapsed = PARTICLES_DELAY / 2;
+        assert_eq!(
+            Some(Vec3::new(32.0 + PARTICLE_SPREAD / 2.0, 0.0, EFFECT_Z)),
+            particle.position()
+        );
+        assert_eq!(None, VisualEffect::new(EffectKind::Flash, 10).position());
+    }
+}
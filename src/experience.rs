@@ -0,0 +1,339 @@
+use crate::base::*;
+use crate::message_log::MessageLog;
+use crate::ui::*;
+use crate::world::*;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// the experience gained each time a talent is used
+pub const USE_EXPERIENCE: u32 = 1;
+// how long a talent change stays on screen, in milliseconds
+pub const NOTIFICATION_DELAY: u64 = 2500;
+
+pub struct ExperiencePlugin;
+
+impl Plugin for ExperiencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TalentEvent>()
+            .add_event::<TalentChangedEvent>()
+            .init_resource::<TalkMemory>()
+            .add_system(talk_experience.in_set(OnUpdate(GameState::Running)))
+            // talents also change in menus and conversations
+            .add_systems((talent_system, talent_notification, fade_notifications).chain());
+    }
+}
+
+/// How a talent is shown to the player
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TalentDefinition {
+    pub code: String,
+    pub name: String,
+    pub description: String,
+    // the sprite shown next to the talent
+    pub icon: String,
+}
+
+/// All the talents of the game, in the order they are shown
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "c5a2e7f1-3b8d-4e96-a0c4-7d1f2b9e6a58"]
+pub struct TalentRegistry {
+    pub talents: Vec<TalentDefinition>,
+}
+
+impl TalentRegistry {
+    fn load(data: &[u8]) -> Result<TalentRegistry, anyhow::Error> {
+        Ok(ron::de::from_bytes(data)?)
+    }
+
+    pub fn get(&self, code: &str) -> Option<&TalentDefinition> {
+        self.talents.iter().find(|t| t.code == code)
+    }
+
+    /// The name of a talent, or its code if it is not defined
+    pub fn name<'a>(&'a self, code: &'a str) -> &'a str {
+        self.get(code).map(|t| t.name.as_str()).unwrap_or(code)
+    }
+}
+
+#[derive(Default)]
+pub struct TalentRegistryAssetLoader;
+
+impl AssetLoader for TalentRegistryAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let registry_asset = TalentRegistry::load(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(registry_asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["talents.ron"]
+    }
+}
+
+/// Something that makes a talent progress, by talent code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TalentEvent {
+    // the talent was used, and may level up
+    Experience(String, u32),
+    // a reward that raises the talent straight away
+    Raise(String, u32),
+}
+
+/// A talent went up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TalentChangedEvent {
+    pub talent: String,
+    pub delta: u32,
+}
+
+impl TalentChangedEvent {
+    pub fn text(&self, registry: &TalentRegistry) -> String {
+        format!("{} +{}", registry.name(&self.talent), self.delta)
+    }
+}
+
+/// Apply a talent event, returning the change to show if there is one
+pub fn apply_talent_event(
+    talents: &mut Talents,
+    event: &TalentEvent,
+) -> Option<TalentChangedEvent> {
+    let (talent, delta) = match event {
+        TalentEvent::Experience(talent, points) => {
+            (talent, talents.gain_experience(talent, *points))
+        }
+        TalentEvent::Raise(talent, points) => {
+            talents.raise(talent, *points);
+            (talent, *points)
+        }
+    };
+    (delta > 0).then(|| TalentChangedEvent {
+        talent: talent.clone(),
+        delta,
+    })
+}
+
+/// The day each character was last talked to, so that talking gives experience once a day
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct TalkMemory {
+    pub days: HashMap<String, u64>,
+}
+
+impl TalkMemory {
+    /// Remember talking to a character, returning whether it is the first time that day
+    pub fn talk(&mut self, character: &str, day: u64) -> bool {
+        self.days.insert(character.to_owned(), day) != Some(day)
+    }
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct Notification {
+    pub remaining: u64,
+}
+
+/// Talking to people or animals is experience in dealing with them
+fn talk_experience(
+    area: Res<Area>,
+    clock: Res<GameClock>,
+    mut memory: ResMut<TalkMemory>,
+    mut event_reader: EventReader<CharacterEvent>,
+    mut talent_events: EventWriter<TalentEvent>,
+) {
+    for e in event_reader
+        .iter()
+        .filter(|e| memory.talk(&e.0, clock.day()))
+    {
+        let talent = match area.character_from_name(&e.0).map(|c| &c.kind) {
+            Some(CharacterKind::Animal) => ANIMALS,
+            _ => PEOPLE,
+        };
+        talent_events.send(TalentEvent::Experience(talent.into(), USE_EXPERIENCE));
+    }
+}
+
+fn talent_system(
+    mut talents: ResMut<Talents>,
+    mut event_reader: EventReader<TalentEvent>,
+    mut changed: EventWriter<TalentChangedEvent>,
+) {
+    for e in event_reader.iter() {
+        if let Some(c) = apply_talent_event(&mut talents, e) {
+            changed.send(c);
+        }
+    }
+}
+
+/// Show the talent changes for a while on top of the screen
+fn talent_notification(
+    mut commands: Commands,
+    handles: Res<AntheaHandles>,
+    registries: Res<Assets<TalentRegistry>>,
+    asset_server: Res<AssetServer>,
+    mut log: ResMut<MessageLog>,
+    mut event_reader: EventReader<TalentChangedEvent>,
+    notification_query: Query<&Notification>,
+) {
+    let Some(registry) = registries.get(&handles.talents_handle) else {
+        return;
+    };
+    let shown = notification_query.iter().count();
+    for (i, e) in event_reader.iter().enumerate() {
+        let text = e.text(registry);
+        log.add(&Message::new(&text, MessageStyle::Info));
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(SCREEN_WIDTH as f32 / 2.0 - 60.0),
+                        top: Val::Px(8.0 + 30.0 * (shown + i) as f32),
+                        ..Default::default()
+                    },
+                    padding: UiRect::all(Val::Px(2.0)),
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..Default::default()
+            })
+            .insert(Notification {
+                remaining: NOTIFICATION_DELAY,
+            })
+            .with_children(|parent| {
+                if let Some(def) = registry.get(&e.talent) {
+                    parent.spawn(ImageBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(24.0), Val::Px(24.0)),
+                            margin: UiRect::right(Val::Px(4.0)),
+                            ..Default::default()
+                        },
+                        image: UiImage::new(asset_server.get_handle(def.icon.as_str())),
+                        ..Default::default()
+                    });
+                }
+                parent.spawn(TextBundle::from_section(
+                    text,
+                    TextStyle {
+                        font: handles.font_handle.clone(),
+                        font_size: 20.0,
+                        color: Color::GOLD,
+                    },
+                ));
+            });
+    }
+}
+
+fn fade_notifications(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut notification_query: Query<(Entity, &mut Notification)>,
+) {
+    let delta = time.delta().as_millis() as u64;
+    for (e, mut notification) in notification_query.iter_mut() {
+        if notification.remaining <= delta {
+            commands.entity(e).despawn_recursive();
+        } else {
+            notification.remaining -= delta;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let mut talents = Talents::default();
+        assert_eq!(EXPERIENCE_STEP, talents.next_level(PEOPLE));
+        assert_eq!(0, talents.gain_experience(PEOPLE, EXPERIENCE_STEP - 1));
+        assert_eq!(1, talents.gain_experience(PEOPLE, 1));
+        assert_eq!(1, talents.value(PEOPLE));
+        assert_eq!(Some(&0), talents.experience.get(PEOPLE));
+        // enough experience for two levels at once
+        assert_eq!(2, talents.gain_experience(PEOPLE, EXPERIENCE_STEP * 5 + 1));
+        assert_eq!(3, talents.value(PEOPLE));
+        assert_eq!(Some(&1), talents.experience.get(PEOPLE));
+        assert_eq!(0, talents.value(WEAPONS));
+    }
+
+    #[test]
+    fn test_talent_event() {
+        let mut talents = Talents::default();
+        assert_eq!(
+            None,
+            apply_talent_event(&mut talents, &TalentEvent::Experience(ANIMALS.into(), 1))
+        );
+        let changed =
+            apply_talent_event(&mut talents, &TalentEvent::Raise(WEAPONS.into(), 2)).unwrap();
+        assert_eq!(2, talents.value(WEAPONS));
+        let registry = TalentRegistry {
+            talents: vec![TalentDefinition {
+                code: WEAPONS.into(),
+                name: "Weapons".into(),
+                description: "Fighting".into(),
+                icon: "sword.png".into(),
+            }],
+        };
+        assert_eq!("Weapons +2", changed.text(&registry));
+        // a talent the registry does not know still shows
+        let unknown = TalentChangedEvent {
+            talent: "magic".into(),
+            delta: 1,
+        };
+        assert_eq!("magic +1", unknown.text(&registry));
+    }
+
+    #[test]
+    fn test_talk_memory() {
+        let mut memory = TalkMemory::default();
+        assert!(memory.talk("Peleus", 0));
+        // bumping into him again the same day teaches nothing
+        assert!(!memory.talk("Peleus", 0));
+        assert!(memory.talk("Cherise", 0));
+        assert!(memory.talk("Peleus", 1));
+        let clock = GameClock {
+            elapsed: (24 - START_HOUR) * GAME_HOUR,
+        };
+        assert_eq!(1, clock.day());
+        assert_eq!(0, clock.hour());
+    }
+
+    #[test]
+    fn test_saved_talents() -> Result<(), anyhow::Error> {
+        let mut talents = Talents::default();
+        talents.set(PEOPLE, 2).gain_experience(ANIMALS, 1);
+        let saved: Talents = ron::de::from_str(&ron::ser::to_string(&talents)?)?;
+        assert_eq!(talents, saved);
+        // saves made when the talents were fixed fields
+        let legacy: Talents =
+            ron::de::from_str("(animals:1,people:2,weapons:0,experience:{People:2})")?;
+        assert_eq!(1, legacy.value(ANIMALS));
+        assert_eq!(2, legacy.value(PEOPLE));
+        assert_eq!(Some(&2), legacy.experience.get(PEOPLE));
+        let legacy: Talents = ron::de::from_str("(animals:0,people:0,weapons:3)")?;
+        assert_eq!(3, legacy.value(WEAPONS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load() -> Result<(), anyhow::Error> {
+        let data = std::fs::read("assets/anthea.talents.ron")?;
+        let registry = TalentRegistry::load(&data)?;
+        for code in [ANIMALS, PEOPLE, WEAPONS] {
+            assert!(registry.get(code).is_some());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use crate::base::*;
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// The positions currently in sight of the player, computed once per step
+#[derive(Debug, Default, Clone, PartialEq, Eq, Resource)]
+pub struct FieldOfView {
+    pub visible: HashSet<SpritePosition>,
+}
+
+impl FieldOfView {
+    pub fn compute(&mut self, state: &AntheaState, radius: i32) -> &mut Self {
+        self.visible = field_of_view(&state.map_position, radius, |p| {
+            state
+                .positions
+                .get(p)
+                .map(|t| !t.transparent)
+                .unwrap_or(true)
+        });
+        self
+    }
+
+    pub fn is_visible(&self, pos: &SpritePosition) -> bool {
+        self.visible.contains(pos)
+    }
+}
+
+/// A slope as an exact fraction, the denominator is always positive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    fn new(num: i32, den: i32) -> Self {
+        Self { num, den }
+    }
+
+    // the slope of the line through the tile's edge closest to the start of the row
+    fn from_tile(depth: i32, col: i32) -> Self {
+        Self::new(2 * col - 1, 2 * depth)
+    }
+
+    // depth * slope, rounded to the nearest column, ties rounded up
+    fn round_ties_up(&self, depth: i32) -> i32 {
+        (2 * depth * self.num + self.den).div_euclid(2 * self.den)
+    }
+
+    // depth * slope, rounded to the nearest column, ties rounded down
+    fn round_ties_down(&self, depth: i32) -> i32 {
+        -(self.den - 2 * depth * self.num).div_euclid(2 * self.den)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    fn cols(&self) -> std::ops::RangeInclusive<i32> {
+        self.start.round_ties_up(self.depth)..=self.end.round_ties_down(self.depth)
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+
+    // a floor tile is only visible if its center is inside the row's slopes,
+    // which makes visibility symmetric
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num
+            && col * self.end.den <= self.depth * self.end.num
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    fn transform(&self, origin: &SpritePosition, depth: i32, col: i32) -> SpritePosition {
+        match self {
+            Quadrant::North => SpritePosition::new(origin.x + col, origin.y - depth),
+            Quadrant::South => SpritePosition::new(origin.x + col, origin.y + depth),
+            Quadrant::East => SpritePosition::new(origin.x + depth, origin.y + col),
+            Quadrant::West => SpritePosition::new(origin.x - depth, origin.y + col),
+        }
+    }
+}
+
+fn in_radius(depth: i32, col: i32, radius: i32) -> bool {
+    4 * (depth * depth + col * col) <= (2 * radius + 1) * (2 * radius + 1)
+}
+
+/// Symmetric shadowcasting: all the positions in sight from the origin within the given radius.
+/// Opaque tiles are visible, but hide what is behind them.
+pub fn field_of_view<F: Fn(&SpritePosition) -> bool>(
+    origin: &SpritePosition,
+    radius: i32,
+    is_opaque: F,
+) -> HashSet<SpritePosition> {
+    let mut visible = HashSet::new();
+    visible.insert(origin.clone());
+    for quadrant in [
+        Quadrant::North,
+        Quadrant::East,
+        Quadrant::South,
+        Quadrant::West,
+    ] {
+        let first = Row {
+            depth: 1,
+            start: Slope::new(-1, 1),
+            end: Slope::new(1, 1),
+        };
+        scan(origin, radius, &quadrant, first, &is_opaque, &mut visible);
+    }
+    visible
+}
+
+fn scan<F: Fn(&SpritePosition) -> bool>(
+    origin: &SpritePosition,
+    radius: i32,
+    quadrant: &Quadrant,
+    mut row: Row,
+    is_opaque: &F,
+    visible: &mut HashSet<SpritePosition>,
+) {
+    if row.depth > radius {
+        return;
+    }
+    let mut prev_opaque: Option<bool> = None;
+    for col in row.cols() {
+        let pos = quadrant.transform(origin, row.depth, col);
+        let opaque = is_opaque(&pos);
+        if (opaque || row.is_symmetric(col)) && in_radius(row.depth, col, radius) {
+            visible.insert(pos);
+        }
+        if prev_opaque == Some(true) && !opaque {
+            row.start = Slope::from_tile(row.depth, col);
+        }
+        if prev_opaque == Some(false) && opaque {
+            let mut next = row.next();
+            next.end = Slope::from_tile(row.depth, col);
+            scan(origin, radius, quadrant, next, is_opaque, visible);
+        }
+        prev_opaque = Some(opaque);
+    }
+    if prev_opaque == Some(false) {
+        scan(origin, radius, quadrant, row.next(), is_opaque, visible);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parse a map where '#' is a wall, '@' the origin, and any other character floor
+    fn parse(
+        map: &str,
+    ) -> (
+        SpritePosition,
+        HashSet<SpritePosition>,
+        HashSet<SpritePosition>,
+    ) {
+        let mut origin = SpritePosition::default();
+        let mut walls = HashSet::new();
+        let mut floors = HashSet::new();
+        for (y, l) in map.lines().enumerate() {
+            for (x, c) in l.trim().chars().enumerate() {
+                let pos = SpritePosition::new(x as i32, y as i32);
+                match c {
+                    '#' => {
+                        walls.insert(pos);
+                    }
+                    '@' => {
+                        origin = pos.clone();
+                        floors.insert(pos);
+                    }
+                    _ => {
+                        floors.insert(pos);
+                    }
+                }
+            }
+        }
+        (origin, walls, floors)
+    }
+
+    fn fov(map: &str, radius: i32) -> (SpritePosition, HashSet<SpritePosition>) {
+        let (origin, walls, floors) = parse(map);
+        let visible = field_of_view(&origin, radius, |p| {
+            walls.contains(p) || !floors.contains(p)
+        });
+        (origin, visible)
+    }
+
+    #[test]
+    fn test_open_room() {
+        let (origin, visible) = fov(
+            ".....
+             .....
+             ..@..
+             .....
+             .....",
+            4,
+        );
+        assert!(visible.contains(&origin));
+        for x in 0..5 {
+            for y in 0..5 {
+                assert!(visible.contains(&SpritePosition::new(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_radius() {
+        let (_origin, visible) = fov(".........@.........", 3);
+        assert!(visible.contains(&SpritePosition::new(6, 0)));
+        assert!(visible.contains(&SpritePosition::new(12, 0)));
+        assert!(!visible.contains(&SpritePosition::new(5, 0)));
+        assert!(!visible.contains(&SpritePosition::new(13, 0)));
+    }
+
+    #[test]
+    fn test_pillar_shadow() {
+        let (_origin, visible) = fov(
+            ".......
+             .@.#...
+             .......",
+            6,
+        );
+        // the pillar itself is visible, not what is right behind it
+        assert!(visible.contains(&SpritePosition::new(3, 1)));
+        assert!(!visible.contains(&SpritePosition::new(4, 1)));
+        assert!(!visible.contains(&SpritePosition::new(5, 1)));
+        // but the sides of the shadow are
+        assert!(visible.contains(&SpritePosition::new(4, 0)));
+        assert!(visible.contains(&SpritePosition::new(4, 2)));
+    }
+
+    #[test]
+    fn test_walls_are_visible() {
+        let (_origin, visible) = fov(
+            "#######
+             #.....#
+             #..@..#
+             #.....#
+             #######",
+            6,
+        );
+        for x in 0..7 {
+            assert!(visible.contains(&SpritePosition::new(x, 0)));
+            assert!(visible.contains(&SpritePosition::new(x, 4)));
+        }
+    }
+
+    #[test]
+    fn test_no_sight_through_walls() {
+        let (_origin, visible) = fov(
+            "..#..
+             .@#..
+             ..#..",
+            6,
+        );
+        assert!(visible.contains(&SpritePosition::new(2, 1)));
+        for y in 0..3 {
+            assert!(!visible.contains(&SpritePosition::new(3, y)));
+            assert!(!visible.contains(&SpritePosition::new(4, y)));
+        }
+    }
+
+    #[test]
+    fn test_corridor() {
+        let (_origin, visible) = fov(
+            "##########
+             @.........
+             ##########",
+            12,
+        );
+        for x in 0..10 {
+            assert!(visible.contains(&SpritePosition::new(x, 1)));
+        }
+    }
+
+    #[test]
+    fn test_diagonal_wall() {
+        // walls touching diagonally hide what is behind them
+        let (_origin, visible) = fov(
+            "@.#.
+             .#..
+             #...
+             ....",
+            6,
+        );
+        assert!(visible.contains(&SpritePosition::new(1, 1)));
+        assert!(visible.contains(&SpritePosition::new(2, 0)));
+        assert!(visible.contains(&SpritePosition::new(0, 2)));
+        assert!(!visible.contains(&SpritePosition::new(2, 2)));
+        assert!(!visible.contains(&SpritePosition::new(3, 3)));
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let map = "..#.......
+                   ....#..#..
+                   .#........
+                   ......#...
+                   ..#.......
+                   .....@..#.
+                   #.........
+                   ...#...#..";
+        let (_origin, walls, floors) = parse(map);
+        let opaque = |p: &SpritePosition| walls.contains(p) || !floors.contains(p);
+        for a in floors.iter() {
+            let from_a = field_of_view(a, 20, opaque);
+            for b in floors.iter() {
+                if from_a.contains(b) {
+                    assert!(
+                        field_of_view(b, 20, opaque).contains(a),
+                        "{:?} sees {:?} but not the other way round",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+}
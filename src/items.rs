@@ -0,0 +1,288 @@
+use crate::base::*;
+use crate::fov::FieldOfView;
+use crate::menu::*;
+use crate::setup::spawn_item;
+use crate::ui::*;
+use crate::world::*;
+use bevy::prelude::*;
+
+// the start of the code of the menus showing what to do with an item
+pub const ITEM: &str = "item";
+const EXAMINE: &str = "examine";
+const USE: &str = "use";
+const DROP: &str = "drop";
+// the start of the code of the actions using an item on something nearby
+const USE_ON: &str = "on";
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<UseItemEvent>()
+            .add_systems(
+                (inventory_item_event, item_action_event).in_set(OnUpdate(GameState::Menu)),
+            )
+            .add_systems((examine_item, drop_item).after(item_action_event));
+    }
+}
+
+/// What the player does with an item of the inventory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemAction {
+    Examine,
+    Use,
+    // use the item on the affordance or character with that name
+    UseOn(String),
+    Drop,
+}
+
+/// An item of the inventory is used, for the area logic to react to
+/// The menu stays open when using an item, for the area logic to show its own menu or close it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UseItemEvent {
+    pub item: String,
+    pub action: ItemAction,
+}
+
+/// The affordances and characters next to a position, as names and descriptions
+pub fn targets_near(area: &Area, position: &SpritePosition) -> Vec<(String, String)> {
+    let mut targets: Vec<(String, String)> = area
+        .affordances
+        .iter()
+        .filter(|(p, _)| position.is_adjacent(p))
+        .map(|(_, a)| (a.name.clone(), a.description.clone()))
+        .chain(
+            area.characters
+                .iter()
+                .filter(|(p, _)| position.is_adjacent(p))
+                .map(|(_, c)| (c.name.clone(), c.description.clone())),
+        )
+        .collect();
+    // affordances can take several tiles
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+/// What can be done with an item, depending on what is around
+pub fn item_menu(item: &Item, targets: &[(String, String)]) -> Menu {
+    let mut items = vec![MenuItem::new(EXAMINE, "Examine")];
+    if item.usable {
+        items.push(MenuItem::new(USE, "Use"));
+        items.extend(targets.iter().map(|(name, description)| {
+            MenuItem::new(
+                format!("{}/{}", USE_ON, name),
+                format!("Use on {}", description),
+            )
+        }));
+    }
+    items.push(MenuItem::new(DROP, "Drop"));
+    Menu::new(format!("{}/{}", ITEM, item.name), &item.description, items)
+}
+
+/// The item and action chosen in an item menu
+fn parse_item_action(menu: &str, code: &str) -> Option<UseItemEvent> {
+    let item = menu.strip_prefix(ITEM)?.strip_prefix('/')?;
+    let action = match code {
+        EXAMINE => ItemAction::Examine,
+        USE => ItemAction::Use,
+        DROP => ItemAction::Drop,
+        _ => ItemAction::UseOn(code.strip_prefix(USE_ON)?.strip_prefix('/')?.to_owned()),
+    };
+    Some(UseItemEvent {
+        item: item.to_owned(),
+        action,
+    })
+}
+
+/// The free tile next to the player to drop an item on, so it is not picked up again straight away
+pub fn drop_position(state: &AntheaState, area: &Area) -> Option<SpritePosition> {
+    let p = &state.map_position;
+    [
+        (0, 1),
+        (1, 0),
+        (-1, 0),
+        (0, -1),
+        (1, 1),
+        (-1, 1),
+        (1, -1),
+        (-1, -1),
+    ]
+    .into_iter()
+    .map(|(dx, dy)| SpritePosition::new(p.x + dx, p.y + dy))
+    .find(|pos| {
+        state
+            .positions
+            .get(pos)
+            .map(|t| t.passable)
+            .unwrap_or_default()
+            && area.item_from_position(pos).is_none()
+            && area.character_from_position(pos).is_none()
+            && area.affordance_from_position(pos).is_none()
+    })
+}
+
+/// Show what can be done with the item chosen in the inventory
+fn inventory_item_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    inventory: Res<Inventory>,
+    state: Res<AntheaState>,
+    area: Res<Area>,
+    menus: ResMut<Menus>,
+    queue: EventWriter<MessageEvent>,
+) {
+    if let Some(item) = event_reader
+        .iter()
+        .filter(|e| e.menu == INVENTORY)
+        .find_map(|e| inventory.items.iter().find(|i| i.name == e.item))
+    {
+        let m = item_menu(item, &targets_near(&area, &state.map_position));
+        push_menu(queue, menus, m);
+    }
+}
+
+fn item_action_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut close_menu: EventWriter<CloseMenuEvent>,
+    mut use_item: EventWriter<UseItemEvent>,
+) {
+    if let Some(e) = event_reader
+        .iter()
+        .find_map(|e| parse_item_action(&e.menu, &e.item))
+    {
+        if matches!(e.action, ItemAction::Examine | ItemAction::Drop) {
+            close_menu.send(CloseMenuEvent);
+        }
+        use_item.send(e);
+    }
+}
+
+fn examine_item(
+    inventory: Res<Inventory>,
+    mut event_reader: EventReader<UseItemEvent>,
+    mut queue: EventWriter<MessageEvent>,
+) {
+    for e in event_reader
+        .iter()
+        .filter(|e| e.action == ItemAction::Examine)
+    {
+        if let Some(item) = inventory.items.iter().find(|i| i.name == e.item) {
+            let text = if item.details.is_empty() {
+                &item.description
+            } else {
+                &item.details
+            };
+            queue.send(MessageEvent::new(text.as_str(), MessageStyle::Info));
+        }
+    }
+}
+
+fn drop_item(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    handles: Res<AntheaHandles>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    fov: Res<FieldOfView>,
+    state: Res<AntheaState>,
+    mut area: ResMut<Area>,
+    mut inventory: ResMut<Inventory>,
+    mut event_reader: EventReader<UseItemEvent>,
+    mut queue: EventWriter<MessageEvent>,
+) {
+    for e in event_reader.iter().filter(|e| e.action == ItemAction::Drop) {
+        let Some(mut item) = inventory.items.iter().find(|i| i.name == e.item).cloned() else {
+            continue;
+        };
+        let Some(pos) = drop_position(&state, &area) else {
+            queue.send(MessageEvent::new(
+                "There is no room to drop it here.",
+                MessageStyle::Info,
+            ));
+            continue;
+        };
+        inventory.remove_item(&item.name);
+        queue.send(MessageEvent::new(
+            format!("{} dropped", item.description),
+            MessageStyle::Info,
+        ));
+        item.position = pos;
+        if let Some(texture_atlas) = texture_atlases.get(&handles.items_texture_atlas_handle) {
+            spawn_item(
+                &mut commands,
+                &asset_server,
+                &handles.items_texture_atlas_handle,
+                texture_atlas,
+                &item,
+                fov.is_visible(&item.position),
+            );
+        }
+        area.add_item(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area() -> Area {
+        let mut area = Area::new("test", 0, SpritePosition::new(1, 1));
+        area.add_affordance(Affordance::new("mirror", "The mirror", 2, 1))
+            .add_character(Character::new("Nerita", "The maid", "", 1, 2))
+            .add_item(Item::new("key", "A key", "", 0, 1));
+        area
+    }
+
+    #[test]
+    fn test_item_menu() {
+        let area = area();
+        let targets = targets_near(&area, &SpritePosition::new(1, 1));
+        assert_eq!(
+            vec![
+                ("Nerita".to_string(), "The maid".to_string()),
+                ("mirror".to_string(), "The mirror".to_string())
+            ],
+            targets
+        );
+        let scissors = Item::new("scissors", "Scissors", "", 0, 0);
+        let m = item_menu(&scissors, &targets);
+        assert_eq!(2, m.items().len());
+        let m = item_menu(&scissors.usable(), &targets);
+        assert_eq!(5, m.items().len());
+        assert_eq!(
+            Some(UseItemEvent {
+                item: "scissors".into(),
+                action: ItemAction::UseOn("mirror".into())
+            }),
+            parse_item_action(m.code(), "on/mirror")
+        );
+        assert_eq!(
+            Some(ItemAction::Drop),
+            parse_item_action(m.code(), DROP).map(|e| e.action)
+        );
+        assert_eq!(None, parse_item_action(INVENTORY, DROP));
+    }
+
+    #[test]
+    fn test_drop_position() {
+        let area = area();
+        let mut state = AntheaState {
+            map_position: SpritePosition::new(1, 1),
+            ..Default::default()
+        };
+        assert_eq!(None, drop_position(&state, &area));
+        for x in 0..3 {
+            for y in 0..3 {
+                state
+                    .positions
+                    .entry(SpritePosition::new(x, y))
+                    .or_default()
+                    .passable = true;
+            }
+        }
+        // not on the character below, the mirror on the right or the key on the left
+        assert_eq!(
+            Some(SpritePosition::new(1, 0)),
+            drop_position(&state, &area)
+        );
+    }
+}
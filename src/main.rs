@@ -3,12 +3,42 @@ use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy_asset_loader::prelude::*;
 
+pub mod animation;
+use animation::*;
+pub mod appearance;
+use appearance::*;
 pub mod base;
 use base::*;
+pub mod checks;
+use checks::*;
+pub mod chunks;
+use chunks::*;
+pub mod conversation;
+use conversation::*;
+pub mod creation;
+use creation::*;
+pub mod effects;
+use effects::*;
+pub mod experience;
+use experience::*;
+pub mod fov;
+use fov::*;
+pub mod items;
+use items::*;
 pub mod menu;
 use menu::*;
+pub mod message_log;
+use message_log::*;
+pub mod minimap;
+use minimap::*;
+pub mod npc;
+use npc::*;
+pub mod pathing;
+use pathing::*;
 pub mod setup;
 use setup::*;
+pub mod spells;
+use spells::*;
 pub mod tiled;
 use tiled::*;
 
@@ -20,8 +50,6 @@ use world::*;
 pub mod stages;
 use stages::castle::*;
 
-use pathfinding::prelude::astar;
-use std::collections::{HashMap, HashSet};
 use std::env;
 
 fn main() {
@@ -66,6 +94,11 @@ impl Plugin for AntheaPlugin {
             .insert_resource(Spells::default())
             .insert_resource(EventMemory::default())
             .insert_resource(MovementPlan::default())
+            .insert_resource(StepState::default())
+            .insert_resource(FieldOfView::default())
+            .insert_resource(Stats::default())
+            .insert_resource(Ending::default())
+            .insert_resource(GameRng::default())
             .add_event::<AffordanceEvent>()
             .add_event::<CharacterEvent>()
             .add_event::<ItemEvent>()
@@ -78,6 +111,12 @@ impl Plugin for AntheaPlugin {
             .init_asset_loader::<MapAssetLoader>()
             .add_asset::<TileSet>()
             .init_asset_loader::<TileSetAssetLoader>()
+            .add_asset::<AnimationSet>()
+            .init_asset_loader::<AnimationSetAssetLoader>()
+            .add_asset::<Appearance>()
+            .init_asset_loader::<AppearanceAssetLoader>()
+            .add_asset::<TalentRegistry>()
+            .init_asset_loader::<TalentRegistryAssetLoader>()
             .add_state::<GameState>()
             .add_loading_state(
                 LoadingState::new(GameState::Setup).continue_to_state(GameState::Title),
@@ -90,21 +129,48 @@ impl Plugin for AntheaPlugin {
                     .chain()
                     .in_schedule(OnEnter(GameState::Start)),
             )
-            .add_system(start_system.in_set(OnUpdate(GameState::Start)))
+            .add_system(start_system.in_schedule(OnEnter(GameState::Creation)))
+            .add_system(show_help.in_schedule(OnEnter(GameState::Running)))
+            // the player is already shown while being created
+            .add_system(
+                camera_follow_system.after(step_system).run_if(
+                    in_state(GameState::Running).or_else(in_state(GameState::Creation)),
+                ),
+            )
             .add_systems(
                 (
                     player_movement_system,
                     automatic_movement_system,
                     move_system,
-                    click_system,
+                    step_system.after(move_system),
+                    chunk_system.after(step_system),
+                    click_system.before(advance_dialogue),
                     pickup_item,
-                    body_change,
+                    equipment_system.before(body_change),
                     journal,
                     remove_tile,
+                    play_time_system,
+                    player_animation_system.after(step_system),
+                    animation_system.after(player_animation_system),
                 )
                     .in_set(OnUpdate(GameState::Running)),
             )
+            // the player can be changed while being created
+            .add_system(
+                body_change.run_if(
+                    in_state(GameState::Running).or_else(in_state(GameState::Creation)),
+                ),
+            )
+            .add_plugin(ConversationPlugin)
+            .add_plugin(CreationPlugin)
+            .add_plugin(EffectsPlugin)
+            .add_plugin(ExperiencePlugin)
+            .add_plugin(ItemsPlugin)
             .add_plugin(MenuPlugin)
+            .add_plugin(MessageLogPlugin)
+            .add_plugin(MinimapPlugin)
+            .add_plugin(NpcPlugin)
+            .add_plugin(SpellsPlugin)
             .add_plugin(UIPlugin);
     }
 }
@@ -112,97 +178,177 @@ impl Plugin for AntheaPlugin {
 fn player_movement_system(
     keyboard_input: Res<Input<KeyCode>>,
     time: Res<Time>,
-    mut state: ResMut<AntheaState>,
+    state: Res<AntheaState>,
+    stage: Res<Area>,
+    mut step: ResMut<StepState>,
     mut msg: EventWriter<MoveEvent>,
 ) {
-    state.last_move += time.delta().as_millis();
-    if state.last_move < MOVE_DELAY {
+    step.last_move += time.delta().as_millis();
+    if step.is_moving() {
+        // queue the next step so that it starts as soon as the current one completes
+        if keyboard_input
+            .get_just_pressed()
+            .any(|k| key_direction(k).is_some())
+        {
+            step.buffered = direction(keyboard_input.get_pressed(), stage.diagonal);
+        }
+        return;
+    }
+    if step.last_move < MOVE_DELAY {
         return;
     }
 
-    //let (mut pos,mut map) = (&mut (state.player_position),&mut state.map_position);
-    for i in keyboard_input.get_pressed() {
-        let mut new_pos = state.map_position.clone();
-        match i {
-            KeyCode::Right => new_pos.x += 1,
-            KeyCode::Left => new_pos.x -= 1,
-            KeyCode::Up => new_pos.y -= 1,
-            KeyCode::Down => new_pos.y += 1,
-            _ => (),
+    let dir = step
+        .buffered
+        .take()
+        .or_else(|| direction(keyboard_input.get_pressed(), stage.diagonal));
+    if let Some(dir) = dir {
+        msg.send(MoveEvent(state.map_position.add(&dir)));
+    }
+}
+
+fn key_direction(key: &KeyCode) -> Option<SpritePosition> {
+    match key {
+        KeyCode::Right | KeyCode::Numpad6 => Some(SpritePosition::new(1, 0)),
+        KeyCode::Left | KeyCode::Numpad4 => Some(SpritePosition::new(-1, 0)),
+        KeyCode::Up | KeyCode::Numpad8 => Some(SpritePosition::new(0, -1)),
+        KeyCode::Down | KeyCode::Numpad2 => Some(SpritePosition::new(0, 1)),
+        KeyCode::Numpad9 => Some(SpritePosition::new(1, -1)),
+        KeyCode::Numpad7 => Some(SpritePosition::new(-1, -1)),
+        KeyCode::Numpad3 => Some(SpritePosition::new(1, 1)),
+        KeyCode::Numpad1 => Some(SpritePosition::new(-1, 1)),
+        _ => None,
+    }
+}
+
+/// The direction of the pressed keys: in eight-direction mode, two arrows pressed together move diagonally
+fn direction<'a>(
+    keys: impl Iterator<Item = &'a KeyCode>,
+    diagonal: bool,
+) -> Option<SpritePosition> {
+    let mut dir: Option<SpritePosition> = None;
+    for d in keys.filter_map(key_direction) {
+        if !diagonal {
+            if d.x == 0 || d.y == 0 {
+                return Some(d);
+            }
+            continue;
         }
-        msg.send(MoveEvent(new_pos));
+        dir = Some(match dir {
+            None => d,
+            Some(o) => SpritePosition::new((o.x + d.x).clamp(-1, 1), (o.y + d.y).clamp(-1, 1)),
+        });
     }
+    dir.filter(|d| d.x != 0 || d.y != 0)
 }
 
 fn move_system(
     mut move_events: EventReader<MoveEvent>,
-    mut state: ResMut<AntheaState>,
+    state: Res<AntheaState>,
+    mut step: ResMut<StepState>,
     stage: ResMut<Area>,
-    mut sprite_query: Query<
-        (&mut Transform, &mut Visibility, &ComputedVisibility),
-        Or<(With<MapTile>, With<Item>, With<Character>)>,
-    >,
     mut msg: EventWriter<ClearMessage>,
     mut ev_affordance: EventWriter<AffordanceEvent>,
     mut ev_character: EventWriter<CharacterEvent>,
     asset_server: Res<AssetServer>,
     audio: Res<Audio>,
+    settings: Res<Settings>,
 ) {
     if let Some(e) = move_events.iter().next() {
+        if step.is_moving() {
+            return;
+        }
         let mut new_pos = e.0.clone();
         if new_pos != state.map_position {
             if let Some(tes) = state.positions.get(&new_pos) {
-                if !tes.passable {
+                if !tes.passable || !corners_clear(&state, &state.map_position, &new_pos) {
                     new_pos.copy(&state.map_position);
                 }
             }
         }
         if new_pos != state.map_position {
-            state.last_move = 0;
-
             //let sprite_position=new_pos.inverse_x();
             if let Some(a) = stage.affordance_from_position(&new_pos) {
                 // println!("Affordance: {}",a.name);
+                step.last_move = 0;
                 ev_affordance.send(AffordanceEvent(a.name.clone()));
             } else if let Some(c) = stage.character_from_position(&new_pos) {
                 //println!("Character: {}",c.name);
+                step.last_move = 0;
                 ev_character.send(CharacterEvent(c.name.clone()));
             } else {
                 msg.send(ClearMessage);
-                audio.play(asset_server.get_handle("sounds/steps.ogg"));
-                let dif_x = ((new_pos.x - state.map_position.x) * SPRITE_SIZE) as f32;
-                let dif_y = ((new_pos.y - state.map_position.y) * SPRITE_SIZE) as f32;
-                state.map_position = new_pos;
-
-                for (mut transform, mut vis, cvis) in &mut sprite_query.iter_mut() {
-                    transform.translation.x -= dif_x;
-                    transform.translation.y += dif_y;
-                    if !cvis.is_visible() && is_visible(&transform.translation, Some(&state)) {
-                        *vis = Visibility::Visible;
-                        let pos = state.map_position.add(&SpritePosition::from_coords(
-                            transform.translation.x,
-                            -transform.translation.y,
-                        ));
-                        //println!("Revealing: {:?}",pos);
-                        state.revealed.insert(pos);
-                    }
+                if settings.sound {
+                    audio.play(asset_server.get_handle("sounds/steps.ogg"));
                 }
+                step.current = Some(Step::new(state.map_position.clone(), new_pos));
             }
         }
     }
 }
 
+fn step_system(
+    time: Res<Time>,
+    mut state: ResMut<AntheaState>,
+    mut step: ResMut<StepState>,
+    stage: Res<Area>,
+    mut fov: ResMut<FieldOfView>,
+    mut stats: ResMut<Stats>,
+    mut player_query: Query<&mut Transform, With<Player>>,
+    mut tile_query: Query<(&mut Visibility, &mut TextureAtlasSprite), With<MapTile>>,
+    mut sprite_query: Query<
+        (&Transform, &mut Visibility),
+        (Without<Player>, Without<MapTile>, Or<(With<Item>, With<Character>)>),
+    >,
+) {
+    let done = if let Some(current) = step.current.as_mut() {
+        current.elapsed += time.delta().as_millis();
+        let from = current.from.to_vec3();
+        let to = current.to.to_vec3();
+        let pos = from.lerp(to, current.progress());
+        for mut transform in player_query.iter_mut() {
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+        }
+        current.is_done()
+    } else {
+        false
+    };
+
+    if done {
+        if let Some(current) = step.current.take() {
+            state.map_position = current.to;
+            stats.steps += 1;
+        }
+        let previous = fov.visible.clone();
+        fov.compute(&state, stage.visibility_from_position(&state.map_position));
+        reveal(&mut state, &fov);
+        show_field_of_view(&state, &fov, &previous, &mut tile_query, &mut sprite_query);
+    }
+}
+
 fn automatic_movement_system(
     mut move_plan: ResMut<MovementPlan>,
     mut msg: EventWriter<MoveEvent>,
+    step: Res<StepState>,
     state: Res<AntheaState>,
+    stage: Res<Area>,
 ) {
-    if state.last_move < MOVE_DELAY {
+    if step.is_moving() || step.last_move < MOVE_DELAY {
         return;
     }
 
-    if let Some(new_pos) = move_plan.0.pop() {
+    let obstacles = obstacles(&stage);
+    if is_blocked(&move_plan, &state, &obstacles) {
+        replan(&mut move_plan, &state, &obstacles, stage.diagonal);
+    }
+    if let Some(new_pos) = move_plan.steps.pop() {
         msg.send(MoveEvent(new_pos));
+    } else if let Some(target) = move_plan.target.take() {
+        // moving into the target fires its event, or picks the item up
+        if is_next_to(&state.map_position, &target, stage.diagonal) {
+            msg.send(MoveEvent(target));
+        }
     }
 }
 
@@ -216,13 +362,18 @@ fn pickup_item(
     mut item_queue: EventWriter<ItemEvent>,
     asset_server: Res<AssetServer>,
     audio: Res<Audio>,
+    settings: Res<Settings>,
+    handles: Res<AntheaHandles>,
+    appearances: Res<Assets<Appearance>>,
 ) {
     if let Some(i) = stage.items.remove(&state.map_position) {
         //println!("Item: {}",i.name);
         for (e, _i2) in item_query.iter().filter(|(_e, i2)| i.name == i2.name) {
             commands.entity(e).despawn_recursive();
         }
-        audio.play(asset_server.get_handle("sounds/pickup.ogg"));
+        if settings.sound {
+            audio.play(asset_server.get_handle("sounds/pickup.ogg"));
+        }
         if i.consumable {
             //queue.send(MessageEvent::new(format!("{} consumed",i.description), MessageStyle::Info));
             item_queue.send(ItemEvent(i.name));
@@ -231,42 +382,45 @@ fn pickup_item(
                 format!("{} picked up", i.description),
                 MessageStyle::Info,
             ));
+            let part = appearances
+                .get(&handles.appearance_handle)
+                .and_then(|a| a.equipment.get(&i.name))
+                .map(|eq| eq.part.clone());
+            let name = i.name.clone();
             inventory.add_item(i);
+            // equipment is put on straight away if the part is free
+            if let Some(part) = part.filter(|p| inventory.equipped_in(p).is_none()) {
+                inventory.equip(&name, part);
+                item_queue.send(ItemEvent(name));
+            }
         }
     }
 }
 
+/// Look around when the game starts
 fn start_system(
-    mouse_button_input: Res<Input<MouseButton>>,
-    mut clearm: EventWriter<ClearMessage>,
-    mut appstate: ResMut<NextState<GameState>>,
     mut state: ResMut<AntheaState>,
+    stage: Res<Area>,
+    mut fov: ResMut<FieldOfView>,
+    mut tile_query: Query<(&mut Visibility, &mut TextureAtlasSprite), With<MapTile>>,
     mut sprite_query: Query<
-        (&Transform, &mut Visibility, &ComputedVisibility),
+        (&Transform, &mut Visibility),
         (
             Without<Help>,
-            Or<(With<MapTile>, With<Item>, With<Character>)>,
+            Without<MapTile>,
+            Or<(With<Item>, With<Character>)>,
         ),
     >,
-    mut help_query: Query<&mut Visibility, With<Help>>,
 ) {
-    if mouse_button_input.just_pressed(MouseButton::Left) {
-        clearm.send(ClearMessage);
-        appstate.set(GameState::Running);
-        for (transform, mut vis, cvis) in &mut sprite_query.iter_mut() {
-            if !cvis.is_visible() && is_visible(&transform.translation, Some(&state)) {
-                *vis = Visibility::Visible;
-                let pos = state.map_position.add(&SpritePosition::from_coords(
-                    transform.translation.x,
-                    -transform.translation.y,
-                ));
-                //println!("Revealing: {:?}",pos);
-                state.revealed.insert(pos);
-            }
-        }
-        for mut vis in &mut help_query.iter_mut() {
-            *vis = Visibility::Visible;
-        }
+    let previous = fov.visible.clone();
+    fov.compute(&state, stage.visibility_from_position(&state.map_position));
+    reveal(&mut state, &fov);
+    show_field_of_view(&state, &fov, &previous, &mut tile_query, &mut sprite_query);
+}
+
+fn show_help(mut help_query: Query<&mut Visibility, With<Help>>) {
+    for mut vis in &mut help_query.iter_mut() {
+        *vis = Visibility::Visible;
     }
 }
 
@@ -282,9 +436,13 @@ fn click_system(
     mut menu: EventWriter<MenuEvent>,
     time: Res<Time>,
     mut move_plan: ResMut<MovementPlan>,
+    dialogue: Res<Dialogue>,
+    mut targeting: ResMut<SpellTargeting>,
+    mut cast: EventWriter<CastSpellEvent>,
 ) {
     let pressed = mouse_button_input.just_pressed(MouseButton::Left);
-    if !pressed {
+    // the click is for the dialogue being read
+    if !pressed || dialogue.is_waiting() {
         return;
     }
     let window = window.get_single().unwrap();
@@ -300,9 +458,9 @@ fn click_system(
             // apply the camera transform
             let pos_wld = camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
             //println!("World coords: {}/{}", pos_wld.x, pos_wld.y);
-            let rel_pos = SpritePosition::from_coords(pos_wld.x, -pos_wld.y);
-
-            let sprite_position = state.map_position.add(&rel_pos);
+            let sprite_position = SpritePosition::from_vec3(&pos_wld.truncate());
+            // position on screen, relative to the center
+            let rel_pos = SpritePosition::from_coords(p.x, -p.y);
             //println!("relative: {:?},{:?}",rel_x,rel_y);
             let ms = time.elapsed().as_millis();
 
@@ -339,6 +497,12 @@ fn click_system(
                 return;
             }
 
+            // the click chooses where the spell goes
+            if let Some(spell) = targeting.0.take() {
+                cast.send(CastSpellEvent::at(spell, sprite_position));
+                return;
+            }
+
             let revealed = state.revealed.contains(&sprite_position);
             /*for rp in state.revealed.iter() {
                 if rp.distance(&rel_pos) <= SPRITE_SIZE / 2 {
@@ -378,13 +542,26 @@ fn click_system(
                     }
                     if dbl_clicked {
                         //println!("Double click");
-                        move_plan.0.clear();
-                        if let Some(tes) = state.positions.get(&sprite_position) {
+                        move_plan.clear();
+                        let obstacles = obstacles(&stage);
+                        if stage.character_from_position(&sprite_position).is_some()
+                            || stage.affordance_from_position(&sprite_position).is_some()
+                            || stage.item_from_position(&sprite_position).is_some()
+                        {
+                            move_plan.steps =
+                                path_next_to(&state, &obstacles, &sprite_position, stage.diagonal);
+                            if !move_plan.steps.is_empty()
+                                || is_next_to(&state.map_position, &sprite_position, stage.diagonal)
+                            {
+                                move_plan.target = Some(sprite_position.clone());
+                            }
+                        } else if let Some(tes) = state.positions.get(&sprite_position) {
                             if tes.passable {
                                 //println!("State positions: {:?}",&state.positions);
                                 //println!("Stage characters: {:?}",&stage.characters);
                                 //println!("Should go from {:?} to {:?}",&state.map_position,sprite_position);
-                                move_plan.0.append(&mut path(state, &sprite_position));
+                                move_plan.steps =
+                                    path(&state, &obstacles, &sprite_position, stage.diagonal);
                             }
                         }
                     }
@@ -398,43 +575,6 @@ fn click_system(
     }
 }
 
-fn successors(
-    pos: &SpritePosition,
-    positions: &HashMap<SpritePosition, TileEntityState>,
-    revealed: &HashSet<SpritePosition>,
-) -> Vec<(SpritePosition, u32)> {
-    vec![
-        SpritePosition::new(pos.x - 1, pos.y),
-        SpritePosition::new(pos.x + 1, pos.y),
-        SpritePosition::new(pos.x, pos.y - 1),
-        SpritePosition::new(pos.x, pos.y + 1),
-    ]
-    .into_iter()
-    .filter(|p| revealed.contains(p))
-    .filter(|p| {
-        if let Some(tes) = positions.get(p) {
-            tes.passable
-        } else {
-            false
-        }
-    })
-    .map(|p| (p, 1))
-    .collect()
-}
-
-fn path(state: Res<AntheaState>, to: &SpritePosition) -> Vec<SpritePosition> {
-    //println!("Should go from {:?} to {:?}",&state.map_position,to);
-    let result = astar(
-        &state.map_position,
-        |p| successors(p, &state.positions, &state.revealed),
-        |p| p.distance(to) / 3,
-        |p| p == to,
-    );
-    let mut v = result.map(|t| t.0).unwrap_or_else(Vec::new);
-    v.reverse();
-    v
-}
-
 fn body_change(
     mut event_reader: EventReader<BodyChangeEvent>,
     asset_server: Res<AssetServer>,
@@ -442,13 +582,16 @@ fn body_change(
     mut event_memory: ResMut<EventMemory>,
     mut sprite_query: Query<(&mut TextureAtlasSprite, &Handle<TextureAtlas>, &PlayerPart)>,
 ) {
-    if let Some(e) = event_reader.iter().next() {
+    for e in event_reader.iter() {
         for (mut sprite, atlas_handle, part) in sprite_query.iter_mut() {
             if part == &e.part {
                 if let Some(texture_atlas) = texture_atlases.get(atlas_handle) {
                     let hair_handle = asset_server.get_handle(e.sprite.as_str());
                     if let Some(hair_index) = texture_atlas.get_texture_index(&hair_handle) {
                         sprite.index = hair_index;
+                        if let Some((r, g, b)) = e.color {
+                            sprite.color = Color::rgb(r, g, b);
+                        }
                         event_memory.body.push(e.clone());
                     } else {
                         eprintln!("Could not find handle for {}", e.sprite);
@@ -461,14 +604,21 @@ fn body_change(
     }
 }
 
+fn play_time_system(time: Res<Time>, mut stats: ResMut<Stats>) {
+    stats.play_time += time.delta().as_millis() as u64;
+}
+
 fn journal(
     mut event_reader: EventReader<JournalEvent>,
     asset_server: Res<AssetServer>,
     audio: Res<Audio>,
+    settings: Res<Settings>,
     mut journal: ResMut<Journal>,
 ) {
     for je in event_reader.iter() {
-        audio.play(asset_server.get_handle("sounds/journal.ogg"));
+        if settings.sound {
+            audio.play(asset_server.get_handle("sounds/journal.ogg"));
+        }
         journal.add_entry(&je.quest, &je.text);
     }
 }
@@ -478,19 +628,27 @@ fn remove_tile(
     mut event_reader: EventReader<RemoveTileEvent>,
     mut event_memory: ResMut<EventMemory>,
     mut state: ResMut<AntheaState>,
+    mut chunks: ResMut<MapChunks>,
     maptile_query: Query<&MapTile>,
 ) {
     for rte in event_reader.iter() {
         if let Some(tes) = state.positions.get_mut(&rte.position) {
             tes.passable = true;
+            let mut removed = vec![];
             for e in tes.entities.iter() {
                 if let Ok(MapTile(layer)) = maptile_query.get(*e) {
                     if *layer == rte.layer {
                         commands.entity(*e).despawn_recursive();
-                        event_memory.removed_tiles.push(rte.clone());
+                        removed.push(*e);
                     }
                 }
             }
+            tes.entities.retain(|e| !removed.contains(e));
+            // the chunk may be spawned again later
+            chunks
+                .remove_tile(&rte.position, rte.layer)
+                .forget_entities(&rte.position, &removed);
+            event_memory.removed_tiles.push(rte.clone());
         }
     }
 }
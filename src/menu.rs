@@ -8,11 +8,20 @@ use std::{
 use crate::ui::*;
 use crate::{
     base::*,
-    setup::{do_setup_map, setup_items, setup_people},
+    checks::GameRng,
+    chunks::MapChunks,
+    creation::CharacterChoices,
+    conversation::PendingResponses,
+    effects::VisualEffect,
+    experience::{Notification, TalentRegistry, TalkMemory},
+    fov::FieldOfView,
+    message_log::MessageLog,
+    setup::{do_setup_map, setup_items, setup_people, tile_color},
+    spells::SpellTargeting,
     tiled::{Map, TileSet},
-    world::{Affordance, Area, Character},
+    world::{Affordance, Area, AreaDefinition, Character},
 };
-use bevy::prelude::*;
+use bevy::{app::AppExit, prelude::*};
 use ron::de::from_str;
 use ron::ser::to_string;
 use serde::{Deserialize, Serialize};
@@ -28,9 +37,23 @@ pub const SYSTEM: &str = "system";
 pub const HELP: &str = "help";
 pub const SAVE: &str = "save";
 pub const LOAD: &str = "load";
+pub const LOAD_ERROR: &str = "load_error";
+pub const TITLE: &str = "title";
+pub const NEW_GAME: &str = "new_game";
+pub const CONTINUE: &str = "continue";
+pub const OPTIONS: &str = "options";
+pub const SOUND: &str = "sound";
+pub const QUIT: &str = "quit";
+pub const END: &str = "end";
+
+// the number of save files the player can choose from
+pub const SAVE_SLOTS: usize = 3;
+pub const SETTINGS_FILE: &str = "settings.ron";
+// the save file from before there were save slots
+pub const OLD_SAVE_FILE: &str = "save.ron";
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd, Eq, Ord, Resource)]
-struct Menus {
+pub struct Menus {
     menus: Vec<Menu>,
     pub journal_index: Option<usize>,
 }
@@ -54,6 +77,79 @@ impl Menus {
     pub fn current(&self) -> &String {
         &self.menus.iter().last().unwrap().code
     }
+
+    /// The title and end menus are never closed, a choice has to be made
+    pub fn must_choose(&self) -> bool {
+        self.menus.len() == 1 && (self.current() == TITLE || self.current() == END)
+    }
+}
+
+/// The save file the player picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource)]
+pub struct SaveSlot(pub usize);
+
+impl Default for SaveSlot {
+    fn default() -> Self {
+        SaveSlot(1)
+    }
+}
+
+/// Go straight to character creation once a new game is set up, instead of showing the title
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Resource)]
+pub struct SkipTitle(pub bool);
+
+pub fn save_path(slot: usize) -> String {
+    format!("save{}.ron", slot)
+}
+
+/// The slot saved last, to continue the game from
+pub fn latest_slot() -> Option<usize> {
+    (1..=SAVE_SLOTS)
+        .filter_map(|slot| {
+            std::fs::metadata(save_path(slot))
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|t| (slot, t))
+        })
+        .max_by_key(|(_, t)| *t)
+        .map(|(slot, _)| slot)
+}
+
+/// Move a save file into the first empty slot, returning that slot
+fn move_to_free_slot(
+    old: &Path,
+    slot_path: impl Fn(usize) -> String,
+) -> std::io::Result<Option<usize>> {
+    if !old.exists() {
+        return Ok(None);
+    }
+    match (1..=SAVE_SLOTS).find(|slot| !Path::new(&slot_path(*slot)).exists()) {
+        Some(slot) => {
+            std::fs::rename(old, slot_path(slot))?;
+            Ok(Some(slot))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Keep the game saved before there were save slots, in a slot
+pub fn migrate_old_save() {
+    if let Err(e) = move_to_free_slot(Path::new(OLD_SAVE_FILE), save_path) {
+        eprintln!("Could not move {} to a save slot: {}", OLD_SAVE_FILE, e);
+    }
+}
+
+pub fn load_settings() -> Settings {
+    std::fs::read_to_string(SETTINGS_FILE)
+        .ok()
+        .and_then(|s| from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_settings(settings: &Settings) {
+    if let Err(e) = std::fs::write(SETTINGS_FILE, to_string(settings).unwrap()) {
+        eprintln!("Could not save settings: {}", e);
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd, Eq, Ord)]
@@ -77,6 +173,14 @@ impl Menu {
             items,
         }
     }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd, Eq, Ord)]
@@ -116,14 +220,126 @@ fn load_item() -> MenuItem {
     MenuItem::new(LOAD, "Load")
 }
 
+fn new_game_item() -> MenuItem {
+    MenuItem::new(NEW_GAME, "New Game")
+}
+
 pub fn system_menu() -> Menu {
     Menu::new(
         SYSTEM,
         "System",
-        vec![help_item(), save_item(), load_item()],
+        vec![help_item(), save_item(), load_item(), new_game_item()],
+    )
+}
+
+pub fn title_menu(has_save: bool) -> Menu {
+    let continue_item = if has_save {
+        MenuItem::new(CONTINUE, "Continue")
+    } else {
+        // nothing to continue from, the item does nothing
+        MenuItem::new("", "Continue (no saved game)")
+    };
+    Menu::new(
+        TITLE,
+        "Anthea's Quest",
+        vec![
+            MenuItem::new("", "You are Anthea, youngest daughter of the king of Selaion. Your father left some months ago to wage war and has never returned. Your brother Peleus now reigns on the throne. You have decided to leave in search of your Father to find out his fate."),
+            MenuItem::new(NEW_GAME, "New Game"),
+            continue_item,
+            MenuItem::new(LOAD, "Load"),
+            MenuItem::new(OPTIONS, "Options"),
+            MenuItem::new(QUIT, "Quit"),
+        ],
+    )
+}
+
+/// The save slots, to save in any of them or to load the ones that exist
+fn slots_menu(code: &str, exists: impl Fn(usize) -> bool) -> Menu {
+    let title = if code == SAVE { "Save" } else { "Load" };
+    let items = (1..=SAVE_SLOTS)
+        .map(|slot| match (exists(slot), code) {
+            (true, _) => MenuItem::new(slot.to_string(), format!("Slot {}", slot)),
+            (false, SAVE) => MenuItem::new(slot.to_string(), format!("Slot {} (empty)", slot)),
+            (false, _) => MenuItem::new("", format!("Slot {} (empty)", slot)),
+        })
+        .collect();
+    Menu::new(code, title, items)
+}
+
+fn save_exists(slot: usize) -> bool {
+    Path::new(&save_path(slot)).exists()
+}
+
+fn options_menu(settings: &Settings) -> Menu {
+    Menu::new(
+        OPTIONS,
+        "Options",
+        vec![MenuItem::new(
+            SOUND,
+            format!("Sound: {}", if settings.sound { "on" } else { "off" }),
+        )],
     )
 }
 
+/// Hours, minutes and seconds of play
+pub fn format_play_time(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h{:02}m", h, m)
+    } else {
+        format!("{}m{:02}s", m, s)
+    }
+}
+
+/// A table row per talent: the defined ones in order, then any other the player has
+fn talent_rows(registry: &TalentRegistry, talents: &Talents) -> Vec<MenuItem> {
+    registry
+        .talents
+        .iter()
+        .map(|t| (t.name.as_str(), t.code.as_str()))
+        .chain(
+            talents
+                .values
+                .keys()
+                .filter(|code| registry.get(code).is_none())
+                .map(|code| (code.as_str(), code.as_str())),
+        )
+        .map(|(name, code)| {
+            MenuItem::new_table(format!("{}:", name), format!("{:>3}", talents.value(code)))
+        })
+        .collect()
+}
+
+pub fn end_menu(
+    ending: &Ending,
+    registry: &TalentRegistry,
+    talents: &Talents,
+    spells: &Spells,
+    journal: &Journal,
+    flags: &QuestFlags,
+    stats: &Stats,
+) -> Menu {
+    let mut items = vec![MenuItem::new("", &ending.text)];
+    items.extend(ending.story.iter().map(|s| MenuItem::new("", s)));
+    let completed = journal
+        .quests
+        .keys()
+        .filter(|q| flags.has_flag(q.as_str(), QUEST_COMPLETED))
+        .count();
+    items.extend(talent_rows(registry, talents));
+    items.extend([
+        MenuItem::new_table("Spells:", format!("{:>3}", spells.spells.len())),
+        MenuItem::new_table("Quests done:", format!("{:>3}", completed)),
+        MenuItem::new_table("Journal:", format!("{:>3}", journal.entries.len())),
+        MenuItem::new_table("Steps:", format!("{:>5}", stats.steps)),
+        MenuItem::new_table("Time:", format_play_time(stats.play_time)),
+        MenuItem::new(TITLE, "Back to the title"),
+        MenuItem::new(NEW_GAME, "New Game"),
+    ]);
+    Menu::new(END, &ending.title, items)
+}
+
 fn journal_item() -> MenuItem {
     MenuItem::new(JOURNAL, "Journal")
 }
@@ -154,7 +370,7 @@ pub fn main_menu() -> Menu {
 }
 
 fn help_menu() -> Menu {
-    Menu::new(HELP, "Help", vec![MenuItem::new("", "Click on your character in the middle of screen for journal, inventory, spells and talents.\nClick everywhere else to see a description.\nUse arrow keys to move.\nMove over an item to pick it up, move into characters and other things to interact.")])
+    Menu::new(HELP, "Help", vec![MenuItem::new("", "Click on your character in the middle of screen for journal, inventory, spells and talents.\nClick everywhere else to see a description.\nUse arrow keys or the numeric keypad to move, press two arrows together to move diagonally.\nPress M to see the map, L to see the last messages and Page Up or Down to scroll them.\nMove over an item to pick it up, move into characters and other things to interact.\nClick on an item in your inventory to examine, use or drop it.\nPress 1 to 9 to cast the spells you know.")])
 }
 
 fn journal_menu(journal: &Journal, menus: &Menus) -> Menu {
@@ -169,7 +385,7 @@ fn inventory_menu(inventory: &Inventory) -> Menu {
     let mut msgs: Vec<MenuItem> = inventory
         .items
         .iter()
-        .map(|i| MenuItem::new("", &i.description))
+        .map(|i| MenuItem::new(&i.name, &i.description))
         .collect();
     if msgs.is_empty() {
         msgs.push(MenuItem::new("", "Empty hands!"));
@@ -177,23 +393,29 @@ fn inventory_menu(inventory: &Inventory) -> Menu {
     Menu::new(INVENTORY, "Inventory", msgs)
 }
 
-fn talents_menu(talents: &Talents) -> Menu {
-    Menu::new(
-        TALENTS,
-        "Talents",
-        vec![
-            MenuItem::new_table("Animals:", format!("{:>3}", talents.animals)),
-            MenuItem::new_table("People:", format!("{:>3}", talents.people)),
-            MenuItem::new_table("Weapons:", format!("{:>3}", talents.weapons)),
-        ],
-    )
+fn talents_menu(registry: &TalentRegistry, talents: &Talents) -> Menu {
+    let mut items = talent_rows(registry, talents);
+    items.extend(
+        registry
+            .talents
+            .iter()
+            .map(|t| MenuItem::new("", format!("{}: {}", t.name, t.description))),
+    );
+    Menu::new(TALENTS, "Talents", items)
 }
 
+/// The known spells, to cast by clicking on them
 fn spells_menu(spells: &Spells) -> Menu {
     let mut msgs: Vec<MenuItem> = spells
         .spells
         .iter()
-        .map(|i| MenuItem::new("", &i.description))
+        .map(|i| match spells.cooldown(&i.name) {
+            0 => MenuItem::new(&i.name, &i.description),
+            left => MenuItem::new(
+                &i.name,
+                format!("{} (in {}s)", i.description, left.div_ceil(1000)),
+            ),
+        })
         .collect();
     if msgs.is_empty() {
         msgs.push(MenuItem::new("", "Empty head!"));
@@ -203,10 +425,16 @@ fn spells_menu(spells: &Spells) -> Menu {
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
+        migrate_old_save();
         app.add_event::<MenuEvent>()
             .add_event::<MenuItemEvent>()
             .add_event::<CloseMenuEvent>()
             .insert_resource(Menus::default())
+            .insert_resource(SaveSlot::default())
+            .insert_resource(load_settings())
+            .insert_resource(SkipTitle::default())
+            .add_system(show_title.in_schedule(OnEnter(GameState::Start)))
+            .add_system(show_end.in_schedule(OnEnter(GameState::End)))
             .add_system(menu_start)
             //.on_state_enter(STAGE, GameState::Menu,show_main_menu)
             .add_systems(
@@ -220,6 +448,10 @@ impl Plugin for MenuPlugin {
                     help_event,
                     save_event,
                     load_event,
+                    title_event,
+                    slot_event,
+                    options_event,
+                    end_event,
                     menu_close,
                     close_menu,
                 )
@@ -228,12 +460,17 @@ impl Plugin for MenuPlugin {
             .add_system(save.in_schedule(OnEnter(GameState::Save)))
             .add_system(clean.in_schedule(OnEnter(GameState::Clean)))
             .add_systems((reset, setup_items, setup_people).in_schedule(OnEnter(GameState::Reset)))
-            .add_system(load.in_schedule(OnEnter(GameState::Load)));
+            .add_system(load.in_schedule(OnEnter(GameState::Load)))
+            .add_system(new_game.in_schedule(OnEnter(GameState::NewGame)));
     }
 }
 
 fn show_menu(mut queue: EventWriter<MessageEvent>, menu: &Menu) {
     //clearm.send(ClearMessage);
+    queue.send(MessageEvent::new_multi(menu_messages(menu)));
+}
+
+fn menu_messages(menu: &Menu) -> Vec<Message> {
     let mut msgs = vec![Message::new(&menu.title, MessageStyle::MenuTitle)];
     if let Some((backward, forward)) = menu.navigation {
         msgs.push(Message::new(
@@ -254,10 +491,10 @@ fn show_menu(mut queue: EventWriter<MessageEvent>, menu: &Menu) {
             ));
         }
     }
-    queue.send(MessageEvent::new_multi(msgs));
+    msgs
 }
 
-fn push_menu(queue: EventWriter<MessageEvent>, mut menus: ResMut<Menus>, menu: Menu) {
+pub fn push_menu(queue: EventWriter<MessageEvent>, mut menus: ResMut<Menus>, menu: Menu) {
     show_menu(queue, &menu);
     menus.push(menu);
 }
@@ -293,6 +530,9 @@ fn click_system(
         if *interaction == Interaction::Clicked {
             let msg = &item.0;
             if CLOSE == msg {
+                if menus.must_choose() {
+                    return;
+                }
                 menus.pop();
                 if let Some(m) = menus.menus.last() {
                     show_menu(queue, m);
@@ -338,7 +578,7 @@ fn close_menu(
     mut menus: ResMut<Menus>,
 ) {
     //for event in keyboard_input_events.iter() {
-    if keyboard_input.just_released(KeyCode::Escape) {
+    if keyboard_input.just_released(KeyCode::Escape) && !menus.must_choose() {
         menus.pop();
         if let Some(m) = menus.menus.last() {
             show_menu(queue, m);
@@ -442,15 +682,20 @@ fn spells_event(
 
 fn talents_event(
     mut event_reader: EventReader<MenuItemEvent>,
+    handles: Res<AntheaHandles>,
+    registries: Res<Assets<TalentRegistry>>,
     talents: Res<Talents>,
     menus: ResMut<Menus>,
     queue: EventWriter<MessageEvent>,
 ) {
+    let Some(registry) = registries.get(&handles.talents_handle) else {
+        return;
+    };
     if let Some(_e) = event_reader
         .iter()
         .find(|e| e.menu == MAIN && e.item == TALENTS)
     {
-        let m = talents_menu(&talents);
+        let m = talents_menu(registry, &talents);
         push_menu(queue, menus, m);
     }
 }
@@ -471,21 +716,24 @@ fn help_event(
 
 fn save_event(
     mut event_reader: EventReader<MenuItemEvent>,
-    mut appstate: ResMut<NextState<GameState>>,
+    menus: ResMut<Menus>,
+    queue: EventWriter<MessageEvent>,
 ) {
     if let Some(_e) = event_reader
         .iter()
         .find(|e| e.menu == SYSTEM && e.item == SAVE)
     {
-        appstate.set(GameState::Save);
+        push_menu(queue, menus, slots_menu(SAVE, save_exists));
     }
 }
 
 fn save(world: &mut World) {
+    world.get_resource_mut::<GameRng>().unwrap().reseed();
     let ss = SaveState::from_world(world);
     let save_string = to_string(&ss).unwrap();
+    let slot = world.get_resource::<SaveSlot>().unwrap().0;
     write!(
-        File::create(Path::new("save.ron")).unwrap(),
+        File::create(Path::new(&save_path(slot))).unwrap(),
         "{}",
         save_string
     )
@@ -503,23 +751,183 @@ fn save(world: &mut World) {
 
 fn load_event(
     mut event_reader: EventReader<MenuItemEvent>,
+    menus: ResMut<Menus>,
+    queue: EventWriter<MessageEvent>,
+) {
+    if let Some(_e) = event_reader
+        .iter()
+        .find(|e| (e.menu == SYSTEM || e.menu == TITLE) && e.item == LOAD)
+    {
+        push_menu(queue, menus, slots_menu(LOAD, save_exists));
+    }
+}
+
+fn slot_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut slot: ResMut<SaveSlot>,
+    mut appstate: ResMut<NextState<GameState>>,
+) {
+    if let Some((e, n)) = event_reader
+        .iter()
+        .filter(|e| e.menu == SAVE || e.menu == LOAD)
+        .find_map(|e| e.item.parse::<usize>().ok().map(|n| (e, n)))
+    {
+        slot.0 = n;
+        if e.menu == SAVE {
+            appstate.set(GameState::Save);
+        } else {
+            appstate.set(GameState::Clean);
+        }
+    }
+}
+
+fn show_title(
+    mut skip: ResMut<SkipTitle>,
+    mut menu: EventWriter<MenuEvent>,
+    mut appstate: ResMut<NextState<GameState>>,
+) {
+    if std::mem::take(skip.as_mut()).0 {
+        appstate.set(GameState::Creation);
+    } else {
+        menu.send(MenuEvent::new(title_menu(latest_slot().is_some())));
+    }
+}
+
+fn title_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut menus: ResMut<Menus>,
+    mut slot: ResMut<SaveSlot>,
+    settings: Res<Settings>,
+    queue: EventWriter<MessageEvent>,
+    mut clearm: EventWriter<ClearMessage>,
     mut appstate: ResMut<NextState<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if let Some(e) = event_reader.iter().find(|e| e.menu == TITLE) {
+        match e.item.as_str() {
+            NEW_GAME => {
+                menus.clear();
+                clearm.send(ClearMessage);
+                appstate.set(GameState::Creation);
+            }
+            CONTINUE => {
+                if let Some(latest) = latest_slot() {
+                    slot.0 = latest;
+                    appstate.set(GameState::Clean);
+                }
+            }
+            OPTIONS => push_menu(queue, menus, options_menu(&settings)),
+            QUIT => exit.send(AppExit),
+            _ => (),
+        }
+    }
+}
+
+fn show_end(
+    ending: Res<Ending>,
+    handles: Res<AntheaHandles>,
+    registries: Res<Assets<TalentRegistry>>,
+    talents: Res<Talents>,
+    spells: Res<Spells>,
+    journal: Res<Journal>,
+    flags: Res<QuestFlags>,
+    stats: Res<Stats>,
+    mut menu: EventWriter<MenuEvent>,
+) {
+    let Some(registry) = registries.get(&handles.talents_handle) else {
+        return;
+    };
+    menu.send(MenuEvent::new(end_menu(
+        &ending, registry, &talents, &spells, &journal, &flags, &stats,
+    )));
+}
+
+/// Starting over in the middle of a game loses what was not saved
+fn new_game_menu() -> Menu {
+    Menu::new(
+        NEW_GAME,
+        "New Game",
+        vec![MenuItem::new(
+            NEW_GAME,
+            "Start again from the beginning? What was not saved is lost.",
+        )],
+    )
+}
+
+/// Start over, going back to the title or straight to a new game
+fn end_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut skip: ResMut<SkipTitle>,
+    mut appstate: ResMut<NextState<GameState>>,
+    menus: ResMut<Menus>,
+    queue: EventWriter<MessageEvent>,
+) {
+    if let Some(e) = event_reader.iter().find(|e| {
+        (e.menu == END && (e.item == TITLE || e.item == NEW_GAME))
+            || ((e.menu == SYSTEM || e.menu == NEW_GAME) && e.item == NEW_GAME)
+    }) {
+        if e.menu == SYSTEM {
+            push_menu(queue, menus, new_game_menu());
+        } else {
+            skip.0 = e.item == NEW_GAME;
+            appstate.set(GameState::NewGame);
+        }
+    }
+}
+
+fn options_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut menus: ResMut<Menus>,
+    mut settings: ResMut<Settings>,
+    queue: EventWriter<MessageEvent>,
 ) {
     if let Some(_e) = event_reader
         .iter()
-        .find(|e| e.menu == SYSTEM && e.item == LOAD)
+        .find(|e| e.menu == OPTIONS && e.item == SOUND)
     {
-        appstate.set(GameState::Clean);
+        settings.sound = !settings.sound;
+        save_settings(&settings);
+        menus.pop();
+        push_menu(queue, menus, options_menu(&settings));
     }
 }
 
-fn clean(world: &mut World) {
+/// Read the game saved in a slot
+pub fn read_save(slot: usize) -> Result<SaveState, anyhow::Error> {
     let mut s = String::new();
-    File::open(Path::new("save.ron"))
-        .unwrap()
-        .read_to_string(&mut s)
-        .unwrap();
-    let ss: SaveState = from_str(&s).unwrap();
+    File::open(Path::new(&save_path(slot)))?.read_to_string(&mut s)?;
+    Ok(from_str(&s)?)
+}
+
+fn load_error_menu(slot: usize) -> Menu {
+    Menu::new(
+        LOAD_ERROR,
+        "Cannot load",
+        vec![MenuItem::new(
+            "",
+            format!("Slot {} is damaged or was saved by an incompatible version.", slot),
+        )],
+    )
+}
+
+fn clean(world: &mut World) {
+    let slot = world.get_resource::<SaveSlot>().unwrap().0;
+    let ss = match read_save(slot) {
+        Ok(ss) => ss,
+        Err(e) => {
+            eprintln!("Could not load slot {}: {}", slot, e);
+            // back to the menu the slot was chosen from, the error on top
+            let m = load_error_menu(slot);
+            let mut queue = world
+                .get_resource_mut::<bevy::ecs::event::Events<MessageEvent>>()
+                .unwrap();
+            queue.send(MessageEvent::new_multi(menu_messages(&m)));
+            world.get_resource_mut::<Menus>().unwrap().push(m);
+            let mut appstate = world.get_resource_mut::<NextState<GameState>>().unwrap();
+            appstate.set(GameState::Menu);
+            return;
+        }
+    };
     ss.clean_world(world);
     world.insert_resource::<SaveState>(ss);
     let mut appstate = world.get_resource_mut::<NextState<GameState>>().unwrap();
@@ -565,6 +973,58 @@ fn load(world: &mut World) {
     clearm.send(ClearMessage);
 }
 
+/// Throw the current game away: the world is set up again from the map onwards
+fn new_game(world: &mut World) {
+    reset_game(world);
+    world.get_resource_mut::<Menus>().unwrap().clear();
+    world
+        .get_resource_mut::<bevy::ecs::event::Events<ClearMessage>>()
+        .unwrap()
+        .send(ClearMessage);
+    let mut appstate = world.get_resource_mut::<NextState<GameState>>().unwrap();
+    appstate.set(GameState::Background);
+}
+
+/// Remove everything the player and the game changed: the area is built again
+/// and all the game resources go back to their initial value
+pub fn reset_game(world: &mut World) {
+    let mut del_query = world.query_filtered::<Entity, Or<(
+        With<Item>,
+        With<MapTile>,
+        With<Character>,
+        With<Player>,
+        With<VisualEffect>,
+        With<Notification>,
+    )>>();
+    let todelete: Vec<Entity> = del_query.iter(world).collect();
+    for e in todelete.into_iter() {
+        despawn_with_children_recursive(world, e);
+    }
+
+    let area = (world.get_resource::<AreaDefinition>().unwrap().0)();
+    world.insert_resource(area);
+    world.insert_resource(AntheaState::default());
+    world.insert_resource(Journal::default());
+    world.insert_resource(Inventory::default());
+    world.insert_resource(Talents::default());
+    world.insert_resource(QuestFlags::default());
+    world.insert_resource(Spells::default());
+    world.insert_resource(EventMemory::default());
+    world.insert_resource(MovementPlan::default());
+    world.insert_resource(StepState::default());
+    world.insert_resource(FieldOfView::default());
+    world.insert_resource(GameClock::default());
+    world.insert_resource(CharacterChoices::default());
+    world.insert_resource(Stats::default());
+    world.insert_resource(TalkMemory::default());
+    world.insert_resource(Ending::default());
+    world.insert_resource(MessageLog::default());
+    world.insert_resource(Dialogue::default());
+    world.insert_resource(SpellTargeting::default());
+    world.insert_resource(PendingResponses::default());
+    world.insert_resource(GameRng::default());
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize, Resource)]
 pub struct SaveState {
     state: AntheaState,
@@ -576,6 +1036,22 @@ pub struct SaveState {
     event_memory: EventMemory,
     area_affordances: HashMap<SpritePosition, Affordance>,
     area_items: HashMap<SpritePosition, Item>,
+    // saves made before characters could move do not have these
+    #[serde(default)]
+    area_characters: Option<HashMap<SpritePosition, Character>>,
+    #[serde(default)]
+    clock: GameClock,
+    #[serde(default)]
+    choices: CharacterChoices,
+    #[serde(default)]
+    stats: Stats,
+    #[serde(default)]
+    log: MessageLog,
+    #[serde(default)]
+    talks: TalkMemory,
+    // the seed of the random numbers to go on with
+    #[serde(default)]
+    seed: Option<u64>,
 }
 
 impl SaveState {
@@ -590,6 +1066,13 @@ impl SaveState {
             event_memory: world.get_resource::<EventMemory>().unwrap().clone(),
             area_affordances: world.get_resource::<Area>().unwrap().affordances.clone(),
             area_items: world.get_resource::<Area>().unwrap().items.clone(),
+            area_characters: Some(world.get_resource::<Area>().unwrap().characters.clone()),
+            clock: world.get_resource::<GameClock>().unwrap().clone(),
+            choices: world.get_resource::<CharacterChoices>().unwrap().clone(),
+            stats: world.get_resource::<Stats>().unwrap().clone(),
+            log: world.get_resource::<MessageLog>().unwrap().clone(),
+            talks: world.get_resource::<TalkMemory>().unwrap().clone(),
+            seed: Some(world.get_resource::<GameRng>().unwrap().seed),
         }
     }
 
@@ -601,10 +1084,23 @@ impl SaveState {
         world.insert_resource::<QuestFlags>(self.flags.clone());
         world.insert_resource::<Spells>(self.spells.clone());
         world.insert_resource::<EventMemory>(self.event_memory.clone());
+        world.insert_resource::<StepState>(StepState::default());
+        world.insert_resource::<FieldOfView>(FieldOfView::default());
+        world.insert_resource::<GameClock>(self.clock.clone());
+        world.insert_resource::<CharacterChoices>(self.choices.clone());
+        world.insert_resource::<Stats>(self.stats.clone());
+        world.insert_resource::<MessageLog>(self.log.clone());
+        world.insert_resource::<TalkMemory>(self.talks.clone());
+        if let Some(seed) = self.seed {
+            world.insert_resource::<GameRng>(GameRng::seeded(seed));
+        }
 
         let mut area = world.get_resource_mut::<Area>().unwrap();
         area.affordances = self.area_affordances.clone();
         area.items = self.area_items.clone();
+        if let Some(characters) = &self.area_characters {
+            area.characters = characters.clone();
+        }
 
         let mut todelete: Vec<Entity> = vec![];
         let mut del_query =
@@ -648,27 +1144,31 @@ impl SaveState {
         let asset_server = world.get_resource::<AssetServer>().unwrap();
         let texture_atlases = world.get_resource::<Assets<TextureAtlas>>().unwrap();
 
-        let mut todo: Vec<(Entity, Handle<TextureAtlas>, String)> = vec![];
+        let mut todo: Vec<(Entity, Handle<TextureAtlas>, &BodyChangeEvent)> = vec![];
 
         for (entity, atlas_handle, part) in part_query.iter(world) {
             for bce in self.event_memory.body.iter() {
                 if part == &bce.part {
-                    todo.push((entity, atlas_handle.clone(), bce.sprite.clone()));
+                    todo.push((entity, atlas_handle.clone(), bce));
                 }
             }
         }
-        let mut todo2: Vec<(Entity, usize)> = vec![];
-        for (entity, atlas_handle, sprite) in todo.into_iter() {
+        let mut todo2: Vec<(Entity, usize, Option<(f32, f32, f32)>)> = vec![];
+        for (entity, atlas_handle, bce) in todo.into_iter() {
             if let Some(texture_atlas) = texture_atlases.get(&atlas_handle) {
-                let hair_handle = asset_server.get_handle(sprite.as_str());
+                let hair_handle = asset_server.get_handle(bce.sprite.as_str());
                 if let Some(hair_index) = texture_atlas.get_texture_index(&hair_handle) {
                     //sprite.index=hair_index as u32;
-                    todo2.push((entity, hair_index));
+                    todo2.push((entity, hair_index, bce.color));
                 }
             }
         }
-        for (entity, hair_index) in todo2.into_iter() {
-            world.get_mut::<TextureAtlasSprite>(entity).unwrap().index = hair_index;
+        for (entity, hair_index, color) in todo2.into_iter() {
+            let mut sprite = world.get_mut::<TextureAtlasSprite>(entity).unwrap();
+            sprite.index = hair_index;
+            if let Some((r, g, b)) = color {
+                sprite.color = Color::rgb(r, g, b);
+            }
         }
 
         let mut todelete: Vec<(Entity, usize)> = vec![];
@@ -682,34 +1182,183 @@ impl SaveState {
                 }
             }
         }
+        let mut chunks = world.get_resource_mut::<MapChunks>().unwrap();
+        for rte in self.event_memory.removed_tiles.iter() {
+            chunks.remove_tile(&rte.position, rte.layer);
+        }
 
         let mut maptile_query = world.query::<&MapTile>();
+        let mut removed: Vec<Entity> = vec![];
         for (e, l) in todelete.iter() {
             if let Ok(MapTile(layer)) = maptile_query.get(world, *e) {
                 if layer == l {
-                    despawn_with_children_recursive(world, *e);
+                    removed.push(*e);
                 }
             }
         }
+        for e in removed.iter() {
+            despawn_with_children_recursive(world, *e);
+        }
+        let mut state = world.get_resource_mut::<AntheaState>().unwrap();
+        for tes in state.positions.values_mut() {
+            tes.entities.retain(|e| !removed.contains(e));
+        }
+        let mut chunks = world.get_resource_mut::<MapChunks>().unwrap();
+        for loaded in chunks.loaded.values_mut() {
+            loaded.retain(|e| !removed.contains(e));
+        }
 
-        let mut sprite_query = world
-            .query_filtered::<(&Transform, &mut Visibility, &ComputedVisibility), (
-                Without<Help>,
-                Or<(With<MapTile>, With<Item>, With<Character>)>,
-            )>();
-        for (transform, mut vis, cvis) in sprite_query.iter_mut(world) {
-            if !cvis.is_visible() {
-                let pos = self
-                    .state
-                    .map_position
-                    .to_relative(&SpritePosition::from_coords(
-                        transform.translation.x,
-                        transform.translation.y,
-                    ));
-                if self.state.revealed.contains(&pos) {
-                    *vis = Visibility::Visible;
-                }
+        let radius = world
+            .get_resource::<Area>()
+            .unwrap()
+            .visibility_from_position(&self.state.map_position);
+        let mut fov = FieldOfView::default();
+        fov.compute(world.get_resource::<AntheaState>().unwrap(), radius);
+
+        let mut tile_query = world
+            .query_filtered::<(&Transform, &mut Visibility, &mut TextureAtlasSprite), With<MapTile>>();
+        for (transform, mut vis, mut sprite) in tile_query.iter_mut(world) {
+            let pos = SpritePosition::from_vec3(&transform.translation);
+            if self.state.revealed.contains(&pos) {
+                *vis = Visibility::Visible;
+                sprite.color = tile_color(&pos, &fov);
+            }
+        }
+        let mut sprite_query = world.query_filtered::<(&Transform, &mut Visibility), (
+            Without<Help>,
+            Without<MapTile>,
+            Or<(With<Item>, With<Character>)>,
+        )>();
+        for (transform, mut vis) in sprite_query.iter_mut(world) {
+            if fov.is_visible(&SpritePosition::from_vec3(&transform.translation)) {
+                *vis = Visibility::Visible;
             }
         }
+        world.insert_resource(fov);
+
+        let mut player_query = world.query_filtered::<&mut Transform, With<Player>>();
+        for mut transform in player_query.iter_mut(world) {
+            let pos = self.state.map_position.to_vec3();
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::EffectKind;
+
+    #[test]
+    fn test_title_menu() {
+        let codes = |m: Menu| m.items.into_iter().map(|i| i.code).collect::<Vec<_>>();
+        assert!(codes(title_menu(true)).contains(&CONTINUE.to_string()));
+        assert!(codes(system_menu()).contains(&NEW_GAME.to_string()));
+        assert!(!codes(title_menu(false)).contains(&CONTINUE.to_string()));
+        assert!(codes(title_menu(false)).contains(&NEW_GAME.to_string()));
+    }
+
+    #[test]
+    fn test_slots_menu() {
+        // empty slots can be saved to but not loaded from
+        let load = slots_menu(LOAD, |slot| slot == 2);
+        assert_eq!(SAVE_SLOTS, load.items.len());
+        let codes = |m: &Menu| m.items.iter().map(|i| i.code.clone()).collect::<Vec<_>>();
+        assert_eq!(vec!["", "2", ""], codes(&load));
+        let save = slots_menu(SAVE, |slot| slot == 2);
+        assert_eq!(vec!["1", "2", "3"], codes(&save));
+        assert_eq!("Slot 1 (empty)", save.items[0].text);
+    }
+
+    #[test]
+    fn test_read_save() {
+        assert!(read_save(SAVE_SLOTS + 1).is_err());
+        assert_eq!(LOAD_ERROR, load_error_menu(SAVE_SLOTS + 1).code());
+    }
+
+    #[test]
+    fn test_move_to_free_slot() {
+        let dir = std::env::temp_dir().join(format!("anthea_slots_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slot_path = |slot| dir.join(save_path(slot)).to_string_lossy().into_owned();
+        let old = dir.join(OLD_SAVE_FILE);
+        assert_eq!(None, move_to_free_slot(&old, slot_path).unwrap());
+        std::fs::write(&old, "old").unwrap();
+        std::fs::write(slot_path(1), "new").unwrap();
+        assert_eq!(Some(2), move_to_free_slot(&old, slot_path).unwrap());
+        assert!(!old.exists());
+        assert_eq!("old", std::fs::read_to_string(slot_path(2)).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reset_game() {
+        let mut world = World::new();
+        world.insert_resource(AreaDefinition(|| {
+            Area::new("test", 0, SpritePosition::new(1, 1))
+        }));
+        let mut area = Area::new("test", 0, SpritePosition::new(1, 1));
+        area.add_item(Item::new("key", "A key", "", 2, 2));
+        world.insert_resource(area);
+        let mut talents = Talents::default();
+        talents.set(PEOPLE, 3);
+        world.insert_resource(talents);
+        world.insert_resource(Stats {
+            steps: 10,
+            play_time: 1000,
+        });
+        world.insert_resource(SpellTargeting(Some("fire".into())));
+        let player = world.spawn(Player).id();
+        let tile = world.spawn(MapTile(0)).id();
+        let camera = world.spawn(MainCamera).id();
+        let effect = world.spawn(VisualEffect::new(EffectKind::Flash, 100)).id();
+
+        reset_game(&mut world);
+        assert!(world.get_resource::<Area>().unwrap().items.is_empty());
+        assert_eq!(
+            &Talents::default(),
+            world.get_resource::<Talents>().unwrap()
+        );
+        assert_eq!(0, world.get_resource::<Stats>().unwrap().steps);
+        assert!(world.get_entity(player).is_none());
+        assert!(world.get_entity(tile).is_none());
+        assert!(world.get_entity(effect).is_none());
+        assert_eq!(None, world.get_resource::<SpellTargeting>().unwrap().0);
+        // the camera and the user interface stay
+        assert!(world.get_entity(camera).is_some());
+    }
+
+    #[test]
+    fn test_end_menu() {
+        let ending = Ending {
+            title: "Done".into(),
+            text: "The end".into(),
+            story: vec!["You did it.".into()],
+        };
+        let stats = Stats {
+            steps: 42,
+            play_time: 125_000,
+        };
+        let mut talents = Talents::default();
+        talents.set("magic", 2);
+        let menu = end_menu(
+            &ending,
+            &TalentRegistry::default(),
+            &talents,
+            &Spells::default(),
+            &Journal::default(),
+            &QuestFlags::default(),
+            &stats,
+        );
+        assert_eq!(END, menu.code);
+        assert_eq!("You did it.", menu.items[1].text);
+        let time = menu.items.iter().find(|i| i.text == "Time:").unwrap();
+        assert_eq!(Some("2m05s".to_string()), time.extra);
+        // talents missing from the registry are still shown
+        let magic = menu.items.iter().find(|i| i.text == "magic:").unwrap();
+        assert_eq!(Some("  2".to_string()), magic.extra);
+        assert_eq!(TITLE, menu.items[menu.items.len() - 2].code);
+        assert_eq!("1h01m", format_play_time(3_660_000));
     }
 }
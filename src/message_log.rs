@@ -0,0 +1,230 @@
+use crate::base::*;
+use crate::ui::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// how many messages the log keeps
+pub const LOG_SIZE: usize = 100;
+// how many messages the log panel shows at once
+pub const LOG_LINES: usize = 12;
+
+pub struct MessageLogPlugin;
+
+impl Plugin for MessageLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MessageLog::default())
+            .add_system(setup_log.in_schedule(OnEnter(GameState::Title)))
+            .add_system(log_system)
+            .add_systems(
+                (toggle_log, scroll_log, show_log.after(scroll_log))
+                    .in_set(OnUpdate(GameState::Running)),
+            )
+            .add_system(hide_log.in_schedule(OnExit(GameState::Running)));
+    }
+}
+
+/// The last messages shown to the player, newest last
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, Resource)]
+pub struct MessageLog {
+    pub messages: VecDeque<Message>,
+    // how many messages back from the newest one the panel shows
+    #[serde(skip)]
+    pub scroll: usize,
+}
+
+impl MessageLog {
+    /// Keep the messages worth reading again: menus and choices are not kept
+    pub fn add(&mut self, msg: &Message) -> &mut Self {
+        if matches!(msg.style, MessageStyle::Title | MessageStyle::Info) && !msg.contents.is_empty()
+        {
+            self.messages.push_back(msg.clone());
+            if self.messages.len() > LOG_SIZE {
+                self.messages.pop_front();
+            }
+            self.scroll = 0;
+        }
+        self
+    }
+
+    /// Scroll back in time for a positive delta, forward for a negative one
+    pub fn scroll_by(&mut self, delta: i32) -> &mut Self {
+        let max = self.messages.len().saturating_sub(LOG_LINES) as i32;
+        self.scroll = (self.scroll as i32 + delta).clamp(0, max) as usize;
+        self
+    }
+
+    /// The messages to show, oldest first
+    pub fn visible(&self) -> impl Iterator<Item = &Message> {
+        let end = self.messages.len().saturating_sub(self.scroll);
+        self.messages.range(end.saturating_sub(LOG_LINES)..end)
+    }
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct LogPanel;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct LogText;
+
+fn setup_log(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(8.0),
+                    bottom: Val::Px(8.0),
+                    ..Default::default()
+                },
+                size: Size::new(
+                    Val::Px(SCREEN_WIDTH as f32 * 0.6),
+                    Val::Px(SCREEN_HEIGHT as f32 * 0.5),
+                ),
+                padding: UiRect::all(Val::Px(8.0)),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::FlexEnd,
+                overflow: Overflow::Hidden,
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.75).into(),
+            visibility: Visibility::Hidden,
+            z_index: ZIndex::Global(1),
+            ..Default::default()
+        })
+        .insert(LogPanel)
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle::default().with_style(Style {
+                    max_size: Size::new(Val::Px(SCREEN_WIDTH as f32 * 0.6 - 16.0), Val::Undefined),
+                    ..Default::default()
+                }))
+                .insert(LogText);
+        });
+}
+
+fn log_system(mut event_reader: EventReader<MessageEvent>, mut log: ResMut<MessageLog>) {
+    for me in event_reader.iter() {
+        for msg in me.messages.iter() {
+            log.add(msg);
+        }
+    }
+}
+
+fn toggle_log(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut log: ResMut<MessageLog>,
+    mut query: Query<&mut Visibility, With<LogPanel>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::L) {
+        for mut vis in query.iter_mut() {
+            *vis = if *vis == Visibility::Hidden {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+        }
+        log.scroll = 0;
+    }
+}
+
+fn scroll_log(keyboard_input: Res<Input<KeyCode>>, mut log: ResMut<MessageLog>) {
+    if keyboard_input.just_pressed(KeyCode::PageUp) {
+        log.scroll_by(LOG_LINES as i32 / 2);
+    } else if keyboard_input.just_pressed(KeyCode::PageDown) {
+        log.scroll_by(-(LOG_LINES as i32) / 2);
+    }
+}
+
+fn show_log(
+    log: Res<MessageLog>,
+    handles: Res<AntheaHandles>,
+    panel_query: Query<&Visibility, (With<LogPanel>, Changed<Visibility>)>,
+    mut text_query: Query<&mut Text, With<LogText>>,
+) {
+    if !log.is_changed() && panel_query.is_empty() {
+        return;
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections = log
+            .visible()
+            .enumerate()
+            .map(|(i, msg)| TextSection {
+                value: format!("{}{}", if i == 0 { "" } else { "\n" }, msg.contents),
+                style: TextStyle {
+                    font: handles.font_handle.clone(),
+                    font_size: 16.0,
+                    color: if msg.style == MessageStyle::Title {
+                        Color::GOLD
+                    } else {
+                        Color::WHITE
+                    },
+                },
+            })
+            .collect();
+    }
+}
+
+fn hide_log(mut query: Query<&mut Visibility, With<LogPanel>>) {
+    for mut vis in query.iter_mut() {
+        *vis = Visibility::Hidden;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(text: &str) -> Message {
+        Message::new(text, MessageStyle::Info)
+    }
+
+    #[test]
+    fn test_add() {
+        let mut log = MessageLog::default();
+        log.add(&info("Hello"))
+            .add(&Message::new("Journal", MessageStyle::MenuTitle))
+            .add(&Message::new(
+                "Yes",
+                MessageStyle::Interaction("yes".into()),
+            ));
+        assert_eq!(
+            vec![info("Hello")],
+            log.messages.iter().cloned().collect::<Vec<_>>()
+        );
+        for i in 0..LOG_SIZE {
+            log.add(&info(&i.to_string()));
+        }
+        assert_eq!(LOG_SIZE, log.messages.len());
+        assert_eq!(info("0"), log.messages[0]);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut log = MessageLog::default();
+        for i in 0..20 {
+            log.add(&info(&i.to_string()));
+        }
+        let first = |log: &MessageLog| log.visible().next().unwrap().contents.clone();
+        assert_eq!(LOG_LINES, log.visible().count());
+        assert_eq!("8", first(&log));
+        log.scroll_by(5);
+        assert_eq!("3", first(&log));
+        // cannot scroll past the oldest message
+        log.scroll_by(5);
+        assert_eq!("0", first(&log));
+        log.scroll_by(-100);
+        assert_eq!(0, log.scroll);
+        // a new message scrolls back to the newest ones
+        log.scroll_by(2).add(&info("new"));
+        assert_eq!("new", log.visible().last().unwrap().contents);
+    }
+
+    #[test]
+    fn test_save() {
+        let mut log = MessageLog::default();
+        log.add(&Message::new("Chapter 1", MessageStyle::Title));
+        let saved = ron::to_string(&log).unwrap();
+        assert_eq!(log, ron::from_str(&saved).unwrap());
+    }
+}
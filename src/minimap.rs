@@ -0,0 +1,429 @@
+use crate::base::*;
+use crate::fov::FieldOfView;
+use crate::world::*;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+
+// tiles shown on each side of the player in the minimap
+pub const MINIMAP_RADIUS: i32 = 12;
+// size of a map tile in the minimap, in pixels
+pub const MINIMAP_SCALE: i32 = 4;
+// space around the full map, in pixels
+const FULL_MAP_MARGIN: i32 = 24;
+
+const UNSEEN: [u8; 4] = [0, 0, 0, 0];
+const FLOOR: [u8; 4] = [200, 190, 160, 255];
+const WALL: [u8; 4] = [90, 80, 70, 255];
+const AFFORDANCE: [u8; 4] = [80, 140, 220, 255];
+const CHARACTER: [u8; 4] = [230, 200, 40, 255];
+const PLAYER: [u8; 4] = [220, 40, 40, 255];
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(setup_minimap.in_schedule(OnEnter(GameState::Start)))
+            .add_system(show_minimap.in_schedule(OnEnter(GameState::Running)))
+            .add_system(hide_minimap.in_schedule(OnExit(GameState::Running)))
+            .add_systems((minimap_system, open_full_map).in_set(OnUpdate(GameState::Running)))
+            .add_system(show_full_map.in_schedule(OnEnter(GameState::Map)))
+            .add_system(close_full_map.in_set(OnUpdate(GameState::Map)))
+            .add_system(hide_full_map.in_schedule(OnExit(GameState::Map)));
+    }
+}
+
+#[derive(Debug, Default, Clone, Resource)]
+pub struct MapImages {
+    pub minimap: Handle<Image>,
+    pub full_map: Handle<Image>,
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct Minimap;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct FullMap;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct FullMapImage;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct RoomLabel;
+
+/// The top left and bottom right corners of the map
+pub fn map_bounds(state: &AntheaState) -> (SpritePosition, SpritePosition) {
+    let mut topleft = state.map_position.clone();
+    let mut bottomright = state.map_position.clone();
+    for pos in state.positions.keys() {
+        topleft.x = topleft.x.min(pos.x);
+        topleft.y = topleft.y.min(pos.y);
+        bottomright.x = bottomright.x.max(pos.x);
+        bottomright.y = bottomright.y.max(pos.y);
+    }
+    (topleft, bottomright)
+}
+
+/// The color of a map position: only revealed positions are drawn,
+/// with the player, the characters in sight and the affordances on top
+fn position_color(
+    state: &AntheaState,
+    area: &Area,
+    fov: &FieldOfView,
+    pos: &SpritePosition,
+) -> [u8; 4] {
+    if pos == &state.map_position {
+        return PLAYER;
+    }
+    if !state.revealed.contains(pos) {
+        return UNSEEN;
+    }
+    if area.characters.contains_key(pos) && fov.is_visible(pos) {
+        CHARACTER
+    } else if area.affordances.contains_key(pos) {
+        AFFORDANCE
+    } else {
+        match state.positions.get(pos) {
+            Some(tes) if tes.passable => FLOOR,
+            Some(_) => WALL,
+            None => UNSEEN,
+        }
+    }
+}
+
+/// The RGBA pixels of the map from the given top left position, one pixel per tile
+pub fn render_map(
+    state: &AntheaState,
+    area: &Area,
+    fov: &FieldOfView,
+    topleft: &SpritePosition,
+    width: i32,
+    height: i32,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in topleft.y..topleft.y + height {
+        for x in topleft.x..topleft.x + width {
+            data.extend_from_slice(&position_color(
+                state,
+                area,
+                fov,
+                &SpritePosition::new(x, y),
+            ));
+        }
+    }
+    data
+}
+
+fn map_image(data: Vec<u8>, width: i32, height: i32) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // keep the tiles sharp when scaled up
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+fn minimap_topleft(state: &AntheaState) -> SpritePosition {
+    state
+        .map_position
+        .add(&SpritePosition::new(-MINIMAP_RADIUS, -MINIMAP_RADIUS))
+}
+
+fn setup_minimap(
+    mut commands: Commands,
+    state: Res<AntheaState>,
+    area: Res<Area>,
+    fov: Res<FieldOfView>,
+    mut images: ResMut<Assets<Image>>,
+    minimap_query: Query<Entity, With<Minimap>>,
+) {
+    if !minimap_query.is_empty() {
+        return;
+    }
+    let side = MINIMAP_RADIUS * 2 + 1;
+    let minimap = images.add(map_image(
+        render_map(&state, &area, &fov, &minimap_topleft(&state), side, side),
+        side,
+        side,
+    ));
+    let full_map = images.add(map_image(vec![0; 4], 1, 1));
+    commands.insert_resource(MapImages {
+        minimap: minimap.clone(),
+        full_map: full_map.clone(),
+    });
+
+    let size = (side * MINIMAP_SCALE) as f32;
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Px(size), Val::Px(size)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            visibility: Visibility::Hidden,
+            z_index: ZIndex::Global(1),
+            ..Default::default()
+        })
+        .insert(Minimap)
+        .with_children(|parent| {
+            parent.spawn(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    ..Default::default()
+                },
+                image: UiImage::new(minimap),
+                ..Default::default()
+            });
+        });
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.85).into(),
+            visibility: Visibility::Hidden,
+            z_index: ZIndex::Global(2),
+            ..Default::default()
+        })
+        .insert(FullMap)
+        .with_children(|parent| {
+            parent
+                .spawn(ImageBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        ..Default::default()
+                    },
+                    image: UiImage::new(full_map),
+                    ..Default::default()
+                })
+                .insert(FullMapImage);
+        });
+}
+
+fn set_minimap_visibility(
+    query: &mut Query<&mut Visibility, With<Minimap>>,
+    visibility: Visibility,
+) {
+    for mut vis in query.iter_mut() {
+        *vis = visibility;
+    }
+}
+
+fn show_minimap(mut query: Query<&mut Visibility, With<Minimap>>) {
+    set_minimap_visibility(&mut query, Visibility::Visible);
+}
+
+fn hide_minimap(mut query: Query<&mut Visibility, With<Minimap>>) {
+    set_minimap_visibility(&mut query, Visibility::Hidden);
+}
+
+fn minimap_system(
+    state: Res<AntheaState>,
+    area: Res<Area>,
+    fov: Res<FieldOfView>,
+    map_images: Res<MapImages>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !state.is_changed() && !area.is_changed() && !fov.is_changed() {
+        return;
+    }
+    if let Some(image) = images.get_mut(&map_images.minimap) {
+        let side = MINIMAP_RADIUS * 2 + 1;
+        image.data = render_map(&state, &area, &fov, &minimap_topleft(&state), side, side);
+    }
+}
+
+fn open_full_map(keyboard_input: Res<Input<KeyCode>>, mut appstate: ResMut<NextState<GameState>>) {
+    if keyboard_input.just_pressed(KeyCode::M) {
+        appstate.set(GameState::Map);
+    }
+}
+
+fn close_full_map(keyboard_input: Res<Input<KeyCode>>, mut appstate: ResMut<NextState<GameState>>) {
+    if keyboard_input.just_pressed(KeyCode::M) || keyboard_input.just_released(KeyCode::Escape) {
+        appstate.set(GameState::Running);
+    }
+}
+
+fn show_full_map(
+    mut commands: Commands,
+    state: Res<AntheaState>,
+    area: Res<Area>,
+    fov: Res<FieldOfView>,
+    handles: Res<AntheaHandles>,
+    map_images: Res<MapImages>,
+    mut images: ResMut<Assets<Image>>,
+    mut root_query: Query<(Entity, &mut Visibility), With<FullMap>>,
+    mut image_query: Query<&mut Style, With<FullMapImage>>,
+    label_query: Query<Entity, With<RoomLabel>>,
+) {
+    let (topleft, bottomright) = map_bounds(&state);
+    let width = bottomright.x - topleft.x + 1;
+    let height = bottomright.y - topleft.y + 1;
+    let scale = ((SCREEN_WIDTH - FULL_MAP_MARGIN * 2) / width)
+        .min((SCREEN_HEIGHT - FULL_MAP_MARGIN * 2) / height)
+        .max(1);
+    let left = (SCREEN_WIDTH - width * scale) / 2;
+    let top = (SCREEN_HEIGHT - height * scale) / 2;
+
+    if let Some(image) = images.get_mut(&map_images.full_map) {
+        *image = map_image(
+            render_map(&state, &area, &fov, &topleft, width, height),
+            width,
+            height,
+        );
+    }
+    for mut style in image_query.iter_mut() {
+        style.position = UiRect {
+            left: Val::Px(left as f32),
+            top: Val::Px(top as f32),
+            ..Default::default()
+        };
+        style.size = Size::new(
+            Val::Px((width * scale) as f32),
+            Val::Px((height * scale) as f32),
+        );
+    }
+
+    for e in label_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    for (root, mut vis) in root_query.iter_mut() {
+        *vis = Visibility::Visible;
+        commands.entity(root).with_children(|parent| {
+            for room in area.rooms.values() {
+                // label the rooms at the middle of their known part
+                let known: Vec<SpritePosition> = room
+                    .dimensions
+                    .iter()
+                    .flat_map(|d| d.positions())
+                    .filter(|p| state.revealed.contains(p))
+                    .collect();
+                if known.is_empty() {
+                    continue;
+                }
+                let x = known.iter().map(|p| p.x).sum::<i32>() / known.len() as i32;
+                let y = known.iter().map(|p| p.y).sum::<i32>() / known.len() as i32;
+                parent
+                    .spawn(
+                        TextBundle::from_section(
+                            &room.description,
+                            TextStyle {
+                                font: handles.font_handle.clone(),
+                                font_size: 14.0,
+                                color: Color::WHITE,
+                            },
+                        )
+                        .with_style(Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                left: Val::Px((left + (x - topleft.x) * scale) as f32),
+                                top: Val::Px((top + (y - topleft.y) * scale) as f32),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        }),
+                    )
+                    .insert(RoomLabel);
+            }
+        });
+    }
+}
+
+fn hide_full_map(mut root_query: Query<&mut Visibility, With<FullMap>>) {
+    for mut vis in root_query.iter_mut() {
+        *vis = Visibility::Hidden;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> AntheaState {
+        let mut state = AntheaState::default();
+        for x in 0..4 {
+            for y in 0..3 {
+                let tes = state
+                    .positions
+                    .entry(SpritePosition::new(x, y))
+                    .or_default();
+                tes.passable = y == 1;
+            }
+        }
+        state.map_position = SpritePosition::new(1, 1);
+        state
+    }
+
+    fn pixel(data: &[u8], width: i32, x: i32, y: i32) -> [u8; 4] {
+        let i = ((y * width + x) * 4) as usize;
+        [data[i], data[i + 1], data[i + 2], data[i + 3]]
+    }
+
+    #[test]
+    fn test_map_bounds() {
+        let state = state();
+        assert_eq!(
+            (SpritePosition::new(0, 0), SpritePosition::new(3, 2)),
+            map_bounds(&state)
+        );
+    }
+
+    #[test]
+    fn test_render_map() {
+        let mut state = state();
+        let area = Area::new("test", 0, SpritePosition::new(1, 1));
+        state.revealed.insert(SpritePosition::new(0, 0));
+        state.revealed.insert(SpritePosition::new(0, 1));
+        let fov = FieldOfView::default();
+        let data = render_map(&state, &area, &fov, &SpritePosition::new(0, 0), 4, 3);
+        assert_eq!(4 * 3 * 4, data.len());
+        assert_eq!(WALL, pixel(&data, 4, 0, 0));
+        assert_eq!(FLOOR, pixel(&data, 4, 0, 1));
+        assert_eq!(PLAYER, pixel(&data, 4, 1, 1));
+        assert_eq!(UNSEEN, pixel(&data, 4, 2, 1));
+    }
+
+    #[test]
+    fn test_render_characters_in_sight() {
+        let mut state = state();
+        let mut area = Area::new("test", 0, SpritePosition::new(1, 1));
+        for x in 2..4 {
+            area.characters.insert(
+                SpritePosition::new(x, 1),
+                Character::new("c", "a character", "", x, 1),
+            );
+        }
+        state.revealed.insert(SpritePosition::new(2, 1));
+        let mut fov = FieldOfView::default();
+        fov.visible.insert(SpritePosition::new(2, 1));
+        let data = render_map(&state, &area, &fov, &SpritePosition::new(0, 0), 4, 3);
+        assert_eq!(CHARACTER, pixel(&data, 4, 2, 1));
+        // characters on unexplored tiles are not known yet
+        assert_eq!(UNSEEN, pixel(&data, 4, 3, 1));
+        // characters out of sight may have moved since
+        state.revealed.insert(SpritePosition::new(3, 1));
+        let data = render_map(&state, &area, &fov, &SpritePosition::new(0, 0), 4, 3);
+        assert_eq!(FLOOR, pixel(&data, 4, 3, 1));
+    }
+}
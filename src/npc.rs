@@ -0,0 +1,317 @@
+use crate::animation::*;
+use crate::base::*;
+use crate::checks::GameRng;
+use crate::fov::FieldOfView;
+use crate::pathing::*;
+use crate::world::*;
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use std::collections::HashSet;
+
+// time between two steps of a character
+pub const NPC_MOVE_DELAY: u128 = 1000;
+
+pub struct NpcPlugin;
+
+impl Plugin for NpcPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GameClock::default())
+            .add_systems((clock_system, npc_system).in_set(OnUpdate(GameState::Running)));
+    }
+}
+
+/// The movement state of a character
+#[derive(Debug, Default, Clone, PartialEq, Eq, Component)]
+pub struct NpcMovement {
+    pub elapsed: u128,
+    pub waypoint: usize,
+}
+
+/// Where a character following a schedule should be at the given hour:
+/// before the first entry of the day, they are still where the last entry sent them
+pub fn scheduled_position(entries: &[(u32, SpritePosition)], hour: u32) -> Option<SpritePosition> {
+    entries
+        .iter()
+        .filter(|(h, _)| *h <= hour)
+        .max_by_key(|(h, _)| *h)
+        .or_else(|| entries.iter().max_by_key(|(h, _)| *h))
+        .map(|(_, p)| p.clone())
+}
+
+/// Where a patrolling or scheduled character is going, moving to the next waypoint once one is reached
+pub fn destination(
+    chr: &Character,
+    waypoint: &mut usize,
+    clock: &GameClock,
+) -> Option<SpritePosition> {
+    match &chr.behaviour {
+        Behaviour::Patrol(points) if !points.is_empty() => {
+            if chr.position == points[*waypoint % points.len()] {
+                *waypoint = (*waypoint + 1) % points.len();
+            }
+            Some(points[*waypoint % points.len()].clone())
+        }
+        Behaviour::Schedule(entries) => scheduled_position(entries, clock.hour()),
+        _ => None,
+    }
+}
+
+/// The next position of a character, if they move
+pub fn next_step<R: Rng>(
+    chr: &Character,
+    waypoint: &mut usize,
+    area: &Area,
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+    clock: &GameClock,
+    rng: &mut R,
+) -> Option<SpritePosition> {
+    let next = match &chr.behaviour {
+        Behaviour::Still => None,
+        Behaviour::Wander(room) => {
+            // wandering characters take their time
+            if !rng.gen_ratio(1, 3) {
+                return None;
+            }
+            let room = area.rooms.get(room)?;
+            let around: Vec<SpritePosition> = successors(
+                &chr.position,
+                state,
+                obstacles,
+                &chr.position,
+                area.diagonal,
+                false,
+            )
+            .into_iter()
+            .map(|(p, _)| p)
+            .filter(|p| room.contains(p))
+            .collect();
+            around.choose(rng).cloned()
+        }
+        _ => destination(chr, waypoint, clock)
+            .filter(|to| to != &chr.position)
+            .and_then(|to| npc_path(state, &chr.position, obstacles, &to, area.diagonal).pop()),
+    };
+    // the destination may be taken
+    next.filter(|p| !obstacles.contains(p))
+}
+
+fn clock_system(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    clock.elapsed += time.delta().as_millis() as u64;
+}
+
+fn npc_system(
+    time: Res<Time>,
+    clock: Res<GameClock>,
+    state: Res<AntheaState>,
+    step: Res<StepState>,
+    fov: Res<FieldOfView>,
+    mut area: ResMut<Area>,
+    mut move_plan: ResMut<MovementPlan>,
+    mut rng: ResMut<GameRng>,
+    mut ev_character: EventReader<CharacterEvent>,
+    mut npc_query: Query<(
+        &mut Character,
+        &mut NpcMovement,
+        &mut Transform,
+        &mut Visibility,
+        Option<&mut Animation>,
+    )>,
+) {
+    let talked_to: HashSet<String> = ev_character.iter().map(|ev| ev.0.clone()).collect();
+    // characters do not walk into each other, the player, or the things lying around
+    let mut obstacles: HashSet<SpritePosition> = area
+        .characters
+        .keys()
+        .chain(area.affordances.keys())
+        .chain(area.items.keys())
+        .cloned()
+        .collect();
+    obstacles.insert(state.map_position.clone());
+    if let Some(current) = &step.current {
+        obstacles.insert(current.to.clone());
+    }
+    for (mut chr, mut mv, mut transform, mut vis, mut anim) in npc_query.iter_mut() {
+        if talked_to.contains(&chr.name) {
+            if let Some(anim) = anim.as_mut() {
+                anim.play(AnimationState::Interact, INTERACT_DELAY);
+            }
+        }
+        mv.elapsed += time.delta().as_millis();
+        if mv.elapsed < NPC_MOVE_DELAY {
+            continue;
+        }
+        mv.elapsed = 0;
+        if let Some(to) = next_step(
+            &chr,
+            &mut mv.waypoint,
+            &area,
+            &state,
+            &obstacles,
+            &clock,
+            &mut rng.rng,
+        ) {
+            let from = chr.position.clone();
+            if let Some(anim) = anim.as_mut() {
+                // walk until the next step is due
+                anim.play(
+                    AnimationState::Walk(Facing::from_move(&from, &to)),
+                    NPC_MOVE_DELAY as u64,
+                );
+            }
+            obstacles.remove(&from);
+            obstacles.insert(to.clone());
+            area.move_character(&from, &to);
+            chr.position = to.clone();
+            let pos = to.to_vec3();
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+            *vis = if fov.is_visible(&to) {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+            // follow the character the player is walking to
+            if move_plan.target.as_ref() == Some(&from) {
+                move_plan.target = Some(to);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::{mock::StepRng, StdRng};
+    use rand::SeedableRng;
+
+    fn state(width: i32, height: i32) -> AntheaState {
+        let mut state = AntheaState::default();
+        for x in 0..width {
+            for y in 0..height {
+                state
+                    .positions
+                    .entry(SpritePosition::new(x, y))
+                    .or_default();
+            }
+        }
+        state.map_position = SpritePosition::new(-1, -1);
+        state
+    }
+
+    #[test]
+    fn test_scheduled_position() {
+        let day = SpritePosition::new(1, 1);
+        let night = SpritePosition::new(2, 2);
+        let entries = vec![(6, day.clone()), (21, night.clone())];
+        assert_eq!(Some(day.clone()), scheduled_position(&entries, 6));
+        assert_eq!(Some(day), scheduled_position(&entries, 20));
+        assert_eq!(Some(night.clone()), scheduled_position(&entries, 23));
+        assert_eq!(Some(night), scheduled_position(&entries, 2));
+        assert_eq!(None, scheduled_position(&[], 2));
+    }
+
+    #[test]
+    fn test_save_clock() {
+        let clock = GameClock {
+            elapsed: 3 * GAME_HOUR,
+        };
+        let saved = ron::to_string(&clock).unwrap();
+        assert_eq!(clock, ron::from_str(&saved).unwrap());
+        assert_eq!(11, clock.hour());
+    }
+
+    #[test]
+    fn test_patrol() {
+        let state = state(5, 1);
+        let area = Area::new("test", 0, SpritePosition::new(0, 0));
+        let mut chr =
+            Character::new("guard", "A guard", "", 0, 0).with_behaviour(Behaviour::Patrol(vec![
+                SpritePosition::new(0, 0),
+                SpritePosition::new(4, 0),
+            ]));
+        let clock = GameClock::default();
+        let mut rng = StepRng::new(0, 1);
+        let mut waypoint = 0;
+        let mut visited = vec![];
+        for _ in 0..8 {
+            if let Some(to) = next_step(
+                &chr,
+                &mut waypoint,
+                &area,
+                &state,
+                &HashSet::new(),
+                &clock,
+                &mut rng,
+            ) {
+                chr.position = to;
+            }
+            visited.push(chr.position.x);
+        }
+        assert_eq!(vec![1, 2, 3, 4, 3, 2, 1, 0], visited);
+    }
+
+    #[test]
+    fn test_blocked() {
+        let state = state(5, 1);
+        let area = Area::new("test", 0, SpritePosition::new(0, 0));
+        let chr = Character::new("guard", "A guard", "", 0, 0)
+            .with_behaviour(Behaviour::Patrol(vec![SpritePosition::new(4, 0)]));
+        let mut obstacles = HashSet::new();
+        obstacles.insert(SpritePosition::new(1, 0));
+        let mut rng = StepRng::new(0, 1);
+        assert_eq!(
+            None,
+            next_step(
+                &chr,
+                &mut 0,
+                &area,
+                &state,
+                &obstacles,
+                &GameClock::default(),
+                &mut rng
+            )
+        );
+    }
+
+    #[test]
+    fn test_wander_in_room() {
+        let state = state(5, 5);
+        let mut area = Area::new("test", 0, SpritePosition::new(0, 0));
+        area.add_room(Room::new("room", "A room", 1, 1, 2, 2));
+        let mut chr = Character::new("maid", "A maid", "", 1, 1)
+            .with_behaviour(Behaviour::Wander("room".into()));
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..50 {
+            if let Some(to) = next_step(
+                &chr,
+                &mut 0,
+                &area,
+                &state,
+                &HashSet::new(),
+                &GameClock::default(),
+                &mut rng,
+            ) {
+                assert_eq!(1, to.distance(&chr.position));
+                chr.position = to;
+            }
+            assert!(area.rooms.get("room").unwrap().contains(&chr.position));
+        }
+    }
+
+    #[test]
+    fn test_move_character() {
+        let mut area = Area::new("test", 0, SpritePosition::new(0, 0));
+        area.add_character(Character::new("maid", "A maid", "", 1, 1));
+        area.move_character(&SpritePosition::new(1, 1), &SpritePosition::new(2, 1));
+        assert!(area
+            .character_from_position(&SpritePosition::new(1, 1))
+            .is_none());
+        assert_eq!(
+            SpritePosition::new(2, 1),
+            area.character_from_position(&SpritePosition::new(2, 1))
+                .unwrap()
+                .position
+        );
+    }
+}
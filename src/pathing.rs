@@ -0,0 +1,367 @@
+use crate::base::*;
+use crate::world::*;
+use pathfinding::prelude::astar;
+use std::collections::HashSet;
+
+// costs are scaled so that a diagonal step costs about sqrt(2) times a straight one
+pub const STRAIGHT_COST: u32 = 10;
+pub const DIAGONAL_COST: u32 = 14;
+
+/// The positions a path cannot go through: the ones where characters stand
+pub fn obstacles(area: &Area) -> HashSet<SpritePosition> {
+    area.characters.keys().cloned().collect()
+}
+
+fn is_passable(state: &AntheaState, pos: &SpritePosition) -> bool {
+    state
+        .positions
+        .get(pos)
+        .map(|t| t.passable)
+        .unwrap_or(false)
+}
+
+/// Whether a move does not cut a wall corner: a diagonal step needs both tiles it squeezes between to be passable
+pub fn corners_clear(state: &AntheaState, from: &SpritePosition, to: &SpritePosition) -> bool {
+    from.x == to.x
+        || from.y == to.y
+        || (is_passable(state, &SpritePosition::new(to.x, from.y))
+            && is_passable(state, &SpritePosition::new(from.x, to.y)))
+}
+
+/// Whether a position can be reached from the other in one step
+pub fn is_next_to(from: &SpritePosition, to: &SpritePosition, diagonal: bool) -> bool {
+    let (dx, dy) = (from.x.abs_diff(to.x), from.y.abs_diff(to.y));
+    if diagonal {
+        dx.max(dy) == 1
+    } else {
+        dx + dy == 1
+    }
+}
+
+/// The estimated cost between two positions: the Manhattan distance with four directions,
+/// the octile distance with eight
+pub fn heuristic(from: &SpritePosition, to: &SpritePosition, diagonal: bool) -> u32 {
+    let (dx, dy) = (from.x.abs_diff(to.x), from.y.abs_diff(to.y));
+    if diagonal {
+        STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+    } else {
+        STRAIGHT_COST * (dx + dy)
+    }
+}
+
+/// The positions that can be walked to from the given one, with their cost.
+/// The player only walks on revealed positions, and obstacles are avoided unless they are the target of the path
+pub fn successors(
+    pos: &SpritePosition,
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+    to: &SpritePosition,
+    diagonal: bool,
+    revealed_only: bool,
+) -> Vec<(SpritePosition, u32)> {
+    let mut v = vec![
+        SpritePosition::new(pos.x - 1, pos.y),
+        SpritePosition::new(pos.x + 1, pos.y),
+        SpritePosition::new(pos.x, pos.y - 1),
+        SpritePosition::new(pos.x, pos.y + 1),
+    ];
+    if diagonal {
+        v.extend([
+            SpritePosition::new(pos.x - 1, pos.y - 1),
+            SpritePosition::new(pos.x + 1, pos.y - 1),
+            SpritePosition::new(pos.x - 1, pos.y + 1),
+            SpritePosition::new(pos.x + 1, pos.y + 1),
+        ]);
+    }
+    v.into_iter()
+        .filter(|p| !revealed_only || state.revealed.contains(p))
+        .filter(|p| p == to || !obstacles.contains(p))
+        .filter(|p| corners_clear(state, pos, p))
+        .filter_map(|p| match state.positions.get(&p) {
+            Some(tes) if tes.passable => {
+                let step = if p.x == pos.x || p.y == pos.y {
+                    STRAIGHT_COST
+                } else {
+                    DIAGONAL_COST
+                };
+                Some((p, tes.cost * step))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The path from the player position to the given one, last step first,
+/// so that the plan can be consumed by popping
+pub fn path(
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+    to: &SpritePosition,
+    diagonal: bool,
+) -> Vec<SpritePosition> {
+    // tiles cost at least 1, so the heuristic never overestimates
+    plan_path(
+        &state.map_position,
+        |p| successors(p, state, obstacles, to, diagonal, true),
+        |p| heuristic(p, to, diagonal),
+        |p| p == to,
+    )
+}
+
+/// The path of a character between two positions, last step first.
+/// Characters know their way around, so the path is not limited to what the player revealed
+pub fn npc_path(
+    state: &AntheaState,
+    from: &SpritePosition,
+    obstacles: &HashSet<SpritePosition>,
+    to: &SpritePosition,
+    diagonal: bool,
+) -> Vec<SpritePosition> {
+    plan_path(
+        from,
+        |p| successors(p, state, obstacles, to, diagonal, false),
+        |p| heuristic(p, to, diagonal),
+        |p| p == to,
+    )
+}
+
+/// The path from the player position to a position next to the given one, to interact with it
+pub fn path_next_to(
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+    to: &SpritePosition,
+    diagonal: bool,
+) -> Vec<SpritePosition> {
+    let last_step = if diagonal {
+        DIAGONAL_COST
+    } else {
+        STRAIGHT_COST
+    };
+    plan_path(
+        &state.map_position,
+        |p| successors(p, state, obstacles, to, diagonal, true),
+        |p| heuristic(p, to, diagonal).saturating_sub(last_step),
+        |p| is_next_to(p, to, diagonal) && corners_clear(state, p, to),
+    )
+}
+
+fn plan_path<N, H, S>(
+    from: &SpritePosition,
+    successors: N,
+    heuristic: H,
+    success: S,
+) -> Vec<SpritePosition>
+where
+    N: Fn(&SpritePosition) -> Vec<(SpritePosition, u32)>,
+    H: Fn(&SpritePosition) -> u32,
+    S: Fn(&SpritePosition) -> bool,
+{
+    let result = astar(from, successors, heuristic, success);
+    let mut v = result.map(|t| t.0).unwrap_or_default();
+    v.reverse();
+    // the path starts with the current position
+    v.pop();
+    v
+}
+
+/// Whether the next step of the plan cannot be taken anymore
+pub fn is_blocked(
+    plan: &MovementPlan,
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+) -> bool {
+    match plan.steps.last() {
+        Some(next) => {
+            let target = plan.target.is_none() && plan.steps.first() == Some(next);
+            (!target && obstacles.contains(next)) || !is_passable(state, next)
+        }
+        None => false,
+    }
+}
+
+/// Plan the path again towards the same destination, or give up if it cannot be reached anymore
+pub fn replan(
+    plan: &mut MovementPlan,
+    state: &AntheaState,
+    obstacles: &HashSet<SpritePosition>,
+    diagonal: bool,
+) {
+    if let Some(target) = plan.target.clone() {
+        plan.steps = path_next_to(state, obstacles, &target, diagonal);
+        if plan.steps.is_empty() && !is_next_to(&state.map_position, &target, diagonal) {
+            plan.target = None;
+        }
+    } else if let Some(to) = plan.steps.first().cloned() {
+        plan.steps = path(state, obstacles, &to, diagonal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // '#' is a wall, ',' grass that costs more, 'c' a character, '@' the player
+    fn parse(map: &str) -> (AntheaState, HashSet<SpritePosition>) {
+        let mut state = AntheaState::default();
+        let mut obstacles = HashSet::new();
+        for (y, l) in map.lines().enumerate() {
+            for (x, c) in l.trim().chars().enumerate() {
+                let pos = SpritePosition::new(x as i32, y as i32);
+                let tes = state.positions.entry(pos.clone()).or_default();
+                match c {
+                    '#' => tes.passable = false,
+                    ',' => tes.cost = 3,
+                    'c' => {
+                        obstacles.insert(pos.clone());
+                    }
+                    '@' => state.map_position = pos.clone(),
+                    _ => (),
+                }
+                state.revealed.insert(pos);
+            }
+        }
+        (state, obstacles)
+    }
+
+    #[test]
+    fn test_path() {
+        let (state, obstacles) = parse(
+            "@...
+             ###.
+             ....",
+        );
+        let to = SpritePosition::new(0, 2);
+        let p = path(&state, &obstacles, &to, false);
+        assert_eq!(8, p.len());
+        assert_eq!(Some(&to), p.first());
+        assert_eq!(Some(&SpritePosition::new(1, 0)), p.last());
+    }
+
+    #[test]
+    fn test_path_unrevealed() {
+        let (mut state, obstacles) = parse("@...");
+        state.revealed.remove(&SpritePosition::new(2, 0));
+        assert!(path(&state, &obstacles, &SpritePosition::new(3, 0), false).is_empty());
+    }
+
+    #[test]
+    fn test_path_costs() {
+        // going around the grass is cheaper than walking through it
+        let (state, obstacles) = parse(
+            "@,,.
+             ....",
+        );
+        let p = path(&state, &obstacles, &SpritePosition::new(3, 0), false);
+        assert_eq!(5, p.len());
+        assert!(!p.contains(&SpritePosition::new(1, 0)));
+    }
+
+    #[test]
+    fn test_path_around_characters() {
+        let (state, obstacles) = parse(
+            "@.c.
+             ....",
+        );
+        let p = path(&state, &obstacles, &SpritePosition::new(3, 0), false);
+        assert_eq!(5, p.len());
+        assert!(!p.contains(&SpritePosition::new(2, 0)));
+        // but a character can be the target, to interact with them
+        let p = path(&state, &obstacles, &SpritePosition::new(2, 0), false);
+        assert_eq!(2, p.len());
+    }
+
+    #[test]
+    fn test_path_next_to() {
+        let (state, obstacles) = parse(
+            "@...
+             ###c",
+        );
+        let target = SpritePosition::new(3, 1);
+        let p = path_next_to(&state, &obstacles, &target, false);
+        assert_eq!(3, p.len());
+        assert_eq!(Some(&SpritePosition::new(3, 0)), p.first());
+        // already next to the target
+        let (state, obstacles) = parse("@c");
+        assert!(path_next_to(&state, &obstacles, &SpritePosition::new(1, 0), false).is_empty());
+    }
+
+    #[test]
+    fn test_replan() {
+        let (state, mut obstacles) = parse(
+            "@...
+             ....",
+        );
+        let mut plan = MovementPlan {
+            steps: path(&state, &obstacles, &SpritePosition::new(3, 0), false),
+            target: None,
+        };
+        assert!(!is_blocked(&plan, &state, &obstacles));
+        // a character steps in the way
+        obstacles.insert(plan.steps.last().unwrap().clone());
+        assert!(is_blocked(&plan, &state, &obstacles));
+        replan(&mut plan, &state, &obstacles, false);
+        assert!(!is_blocked(&plan, &state, &obstacles));
+        assert_eq!(Some(&SpritePosition::new(3, 0)), plan.steps.first());
+        assert_eq!(5, plan.steps.len());
+    }
+
+    #[test]
+    fn test_npc_path() {
+        let (mut state, obstacles) = parse(
+            "@...
+             .#..
+             ....",
+        );
+        state.revealed.clear();
+        let from = SpritePosition::new(3, 2);
+        let to = SpritePosition::new(0, 2);
+        assert!(path(&state, &obstacles, &to, false).is_empty());
+        assert_eq!(3, npc_path(&state, &from, &obstacles, &to, false).len());
+    }
+
+    #[test]
+    fn test_heuristic() {
+        let from = SpritePosition::new(0, 0);
+        let to = SpritePosition::new(3, 1);
+        assert_eq!(40, heuristic(&from, &to, false));
+        assert_eq!(34, heuristic(&from, &to, true));
+    }
+
+    #[test]
+    fn test_diagonal_path() {
+        let (state, obstacles) = parse(
+            "@...
+             ....
+             ....",
+        );
+        let to = SpritePosition::new(2, 2);
+        assert_eq!(4, path(&state, &obstacles, &to, false).len());
+        assert_eq!(2, path(&state, &obstacles, &to, true).len());
+    }
+
+    #[test]
+    fn test_no_corner_cutting() {
+        let (state, obstacles) = parse(
+            "@#.
+             ...",
+        );
+        let to = SpritePosition::new(1, 1);
+        assert!(!corners_clear(&state, &state.map_position, &to));
+        // the wall corner cannot be cut, so the diagonal path goes around it
+        let p = path(&state, &obstacles, &SpritePosition::new(2, 0), true);
+        assert_eq!(4, p.len());
+        assert_eq!(Some(&SpritePosition::new(0, 1)), p.last());
+    }
+
+    #[test]
+    fn test_diagonal_next_to() {
+        let (state, obstacles) = parse(
+            "@..
+             ...
+             ..c",
+        );
+        let target = SpritePosition::new(2, 2);
+        assert_eq!(1, path_next_to(&state, &obstacles, &target, true).len());
+        assert_eq!(3, path_next_to(&state, &obstacles, &target, false).len());
+    }
+}
@@ -1,7 +1,13 @@
+use crate::animation::{Animation, PLAYER_ANIMATIONS};
+use crate::appearance::*;
 use crate::base::*;
+use crate::chunks::*;
+use crate::fov::*;
+use crate::npc::NpcMovement;
 use crate::tiled::*;
 use crate::world::*;
-use bevy::{prelude::*, sprite::TextureAtlasBuilder};
+use bevy::{ecs::query::ReadOnlyWorldQuery, prelude::*, sprite::TextureAtlasBuilder};
+use std::collections::HashSet;
 
 pub fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default()).insert(MainCamera);
@@ -59,6 +65,9 @@ pub fn do_setup_map(
     let atlas_handle = texture_atlases.add(texture_atlas);
     let texture_atlas = texture_atlases.get(&atlas_handle).unwrap();
 
+    // tiles are only spawned for the chunks around the player
+    let mut chunks = MapChunks::new(atlas_handle.clone());
+    state.positions.clear();
     for (ix, l) in map.layers.iter().enumerate() {
         let mut pos = SpritePosition::new(0, 0);
         //let start = SpritePosition::new(state.map_position.x,state.map_position.y);
@@ -68,24 +77,16 @@ pub fn do_setup_map(
                 let path = &ts.tiles[t - 1];
                 let tile_handle = asset_server.get_handle(path.as_str());
                 let tile_index = texture_atlas.get_texture_index(&tile_handle).unwrap();
-                let mut rel_pos = pos.to_relative(&state.map_position);
-                rel_pos.y = -rel_pos.y;
-                let vec3 = rel_pos.to_vec3();
-                let ec = commands
-                    .spawn(SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new(tile_index),
-                        texture_atlas: atlas_handle.clone(),
-                        transform: Transform::from_translation(vec3),
-                        visibility: Visibility::Hidden,
-                        ..Default::default()
-                    })
-                    .insert(MapTile(ix))
-                    .id();
+                chunks.add_tile(ChunkTile {
+                    position: pos.clone(),
+                    layer: ix,
+                    index: tile_index,
+                });
 
                 let e = state.positions.entry(pos.clone()).or_default();
-                e.entities.push(ec);
                 let pass = is_tile_passable(path);
                 e.passable = e.passable && pass;
+                e.cost = e.cost.max(tile_cost(path));
                 if ix == 0 && !pass {
                     e.transparent = false;
                 }
@@ -100,17 +101,53 @@ pub fn do_setup_map(
             }
         }
     }
+    // the field of view is computed once the game starts
+    update_chunks(
+        &mut commands,
+        &mut chunks,
+        &mut state,
+        &FieldOfView::default(),
+    );
+    commands.insert_resource(chunks);
 
     //println!("finished map");
     //println!("Revealed: {:?}",state.revealed);
 }
 
+/// Show an item on the map, with the sprite taken from the items texture atlas
+pub fn spawn_item(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    atlas_handle: &Handle<TextureAtlas>,
+    texture_atlas: &TextureAtlas,
+    item: &Item,
+    visible: bool,
+) {
+    let item_handle = asset_server.get_handle(item.sprite.as_str());
+    let item_index = texture_atlas.get_texture_index(&item_handle).unwrap();
+    let pos = item.position.to_vec3_z(0.3);
+    commands
+        .spawn(SpriteSheetBundle {
+            sprite: TextureAtlasSprite::new(item_index),
+            texture_atlas: atlas_handle.clone(),
+            transform: Transform::from_translation(pos),
+            visibility: if visible {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            },
+            ..Default::default()
+        })
+        .insert(item.clone());
+}
+
 pub fn setup_items(
     mut commands: Commands,
-    sprite_handles: Res<AntheaHandles>,
+    mut sprite_handles: ResMut<AntheaHandles>,
     asset_server: Res<AssetServer>,
     stage: Res<Area>,
-    state: ResMut<AntheaState>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    help_query: Query<Entity, With<Help>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
 ) {
@@ -124,36 +161,33 @@ pub fn setup_items(
     let atlas_handle = texture_atlases.add(texture_atlas);
     let texture_atlas = texture_atlases.get(&atlas_handle).unwrap();
     for item in stage.items.values() {
-        let item_handle = asset_server.get_handle(item.sprite.as_str());
-        let item_index = texture_atlas.get_texture_index(&item_handle).unwrap();
-        let pos = SpritePosition {
-            x: item.position.x - state.map_position.x,
-            y: state.map_position.y - item.position.y,
-        }
-        .to_vec3_z(0.3);
-        let vis = false; //is_visible(&pos,None);
-        commands
-            .spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(item_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos),
-                visibility: if vis {
-                    Visibility::Visible
-                } else {
-                    Visibility::Hidden
-                },
-                ..Default::default()
-            })
-            .insert(item.clone());
+        // shown once in the field of view
+        spawn_item(
+            &mut commands,
+            &asset_server,
+            &atlas_handle,
+            texture_atlas,
+            item,
+            false,
+        );
     }
+    sprite_handles.items_texture_atlas_handle = atlas_handle.clone();
 
+    // the help icon survives reloads, only spawn it once
+    if !help_query.is_empty() {
+        return;
+    }
     let item_handle = asset_server.get_handle("sprites/items/help.png");
     let item_index = texture_atlas.get_texture_index(&item_handle).unwrap();
-    let pos = Vec3::new(
+    let mut pos = Vec3::new(
         (-SCREEN_WIDTH / 2 + SPRITE_SIZE / 2) as f32,
         (SCREEN_HEIGHT / 2 - SPRITE_SIZE / 2) as f32,
         0.3,
     );
+    if let Some(camera_transform) = camera_query.iter().next() {
+        pos.x += camera_transform.translation.x;
+        pos.y += camera_transform.translation.y;
+    }
     commands
         .spawn(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(item_index),
@@ -162,13 +196,17 @@ pub fn setup_items(
             visibility: Visibility::Hidden,
             ..Default::default()
         })
-        .insert(Help);
+        .insert(Help)
+        .insert(ScreenAnchored);
 }
 
 pub fn setup_body(
     mut commands: Commands,
     sprite_handles: Res<AntheaHandles>,
     asset_server: Res<AssetServer>,
+    state: Res<AntheaState>,
+    inventory: Res<Inventory>,
+    appearances: Res<Assets<Appearance>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
 ) {
@@ -181,63 +219,29 @@ pub fn setup_body(
 
     let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
 
-    let body_handle = asset_server.get_handle("sprites/people/human_f.png");
-    let body_index = texture_atlas.get_texture_index(&body_handle).unwrap();
-
-    let hair_handle = asset_server.get_handle("sprites/people/fem_black.png");
-    let hair_index = texture_atlas.get_texture_index(&hair_handle).unwrap();
-
-    let pants_handle = asset_server.get_handle("sprites/people/pants_l_white.png");
-    let pants_index = texture_atlas.get_texture_index(&pants_handle).unwrap();
-
-    let top_handle = asset_server.get_handle("sprites/people/shirt_white1.png");
-    let top_index = texture_atlas.get_texture_index(&top_handle).unwrap();
-
-    let hand_handle = asset_server.get_handle("sprites/people/empty.png");
-    let hand_index = texture_atlas.get_texture_index(&hand_handle).unwrap();
+    let appearance = appearances.get(&sprite_handles.appearance_handle).unwrap();
+    let parts = part_indices(appearance, &inventory, &asset_server, &texture_atlas);
 
     let atlas_handle = texture_atlases.add(texture_atlas);
 
-    let pos = Vec3::new(0.0, 0.0, 0.3);
     commands
-        .spawn((Player, SpatialBundle::default()))
+        .spawn((
+            Player,
+            Animation::new(PLAYER_ANIMATIONS),
+            SpatialBundle::from_transform(Transform::from_translation(
+                state.map_position.to_vec3(),
+            )),
+        ))
         .with_children(|p| {
-            p.spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(body_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos),
-                ..Default::default()
-            })
-            .insert(PlayerPart::Body);
-            let pos2 = Vec3::new(0.0, 0.0, 0.4);
-            p.spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(pants_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos2),
-                ..Default::default()
-            })
-            .insert(PlayerPart::Pants);
-            p.spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(top_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos2),
-                ..Default::default()
-            })
-            .insert(PlayerPart::Top);
-            p.spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(hair_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos2),
-                ..Default::default()
-            })
-            .insert(PlayerPart::Hair);
-            p.spawn(SpriteSheetBundle {
-                sprite: TextureAtlasSprite::new(hand_index),
-                texture_atlas: atlas_handle.clone(),
-                transform: Transform::from_translation(pos2),
-                ..Default::default()
-            })
-            .insert(PlayerPart::RightHand);
+            for (part, index) in parts.into_iter() {
+                p.spawn(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite::new(index),
+                    texture_atlas: atlas_handle.clone(),
+                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, part.z())),
+                    ..Default::default()
+                })
+                .insert(part);
+            }
         });
 }
 
@@ -246,7 +250,6 @@ pub fn setup_people(
     sprite_handles: Res<AntheaHandles>,
     asset_server: Res<AssetServer>,
     stage: Res<Area>,
-    state: Res<AntheaState>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
 ) {
@@ -265,11 +268,7 @@ pub fn setup_people(
     for chr in stage.characters.values() {
         let chr_handle = asset_server.get_handle(chr.sprite.as_str());
         let chr_index = texture_atlas.get_texture_index(&chr_handle).unwrap();
-        let pos = SpritePosition {
-            x: chr.position.x - state.map_position.x,
-            y: state.map_position.y - chr.position.y,
-        }
-        .to_vec3_z(0.3);
+        let pos = chr.position.to_vec3_z(0.3);
         let vis = false; //is_visible(&pos,None);
         commands
             .spawn(SpriteSheetBundle {
@@ -283,44 +282,86 @@ pub fn setup_people(
                 },
                 ..Default::default()
             })
-            .insert(chr.clone());
+            .insert(chr.clone())
+            .insert(NpcMovement::default())
+            .insert(Animation::new(chr.name.clone()));
     }
 }
 
-pub fn is_visible(pos: &Vec3, ostate: Option<&AntheaState>) -> bool {
-    if pos.x.abs() < VISIBILITY_DISTANCE && pos.y.abs() < VISIBILITY_DISTANCE {
-        if let Some(state) = ostate {
-            let mut d_x = pos.x;
-            let mut d_y = pos.y;
-            while d_x != 0.0 || d_y != 0.0 {
-                let mut n_x = d_x;
-                if d_x != 0.0 && d_x.abs() >= d_y.abs() {
-                    n_x = if d_x < 0.0 {
-                        d_x + SPRITE_SIZE as f32
-                    } else {
-                        d_x - SPRITE_SIZE as f32
-                    };
-                }
-                if d_y != 0.0 && d_x.abs() <= d_y.abs() {
-                    d_y = if d_y < 0.0 {
-                        d_y + SPRITE_SIZE as f32
-                    } else {
-                        d_y - SPRITE_SIZE as f32
-                    };
-                }
-                d_x = n_x;
+/// Reveal the map positions in the field of view, returning the ones that were not revealed yet
+pub fn reveal(state: &mut AntheaState, fov: &FieldOfView) -> Vec<SpritePosition> {
+    let v: Vec<SpritePosition> = fov
+        .visible
+        .iter()
+        .filter(|pos| state.positions.contains_key(pos) && !state.revealed.contains(pos))
+        .cloned()
+        .collect();
+    for pos in v.iter() {
+        state.revealed.insert(pos.clone());
+    }
+    v
+}
 
-                let pos = state
-                    .map_position
-                    .add(&SpritePosition::from_coords(d_x, -d_y));
-                if let Some(t) = state.positions.get(&pos) {
-                    if !t.transparent {
-                        return false;
-                    }
+/// The tint of a revealed tile: full colors when in sight, dimmed when only remembered
+pub fn tile_color(pos: &SpritePosition, fov: &FieldOfView) -> Color {
+    if fov.is_visible(pos) {
+        Color::WHITE
+    } else {
+        REMEMBERED_COLOR
+    }
+}
+
+/// Update the tiles that entered or left the field of view, and show only the items and characters in sight
+pub fn show_field_of_view<F: ReadOnlyWorldQuery>(
+    state: &AntheaState,
+    fov: &FieldOfView,
+    previous: &HashSet<SpritePosition>,
+    tile_query: &mut Query<(&mut Visibility, &mut TextureAtlasSprite), With<MapTile>>,
+    sprite_query: &mut Query<(&Transform, &mut Visibility), F>,
+) {
+    for pos in previous.union(&fov.visible) {
+        if !state.revealed.contains(pos) {
+            continue;
+        }
+        if let Some(tes) = state.positions.get(pos) {
+            for e in tes.entities.iter() {
+                if let Ok((mut vis, mut sprite)) = tile_query.get_mut(*e) {
+                    *vis = Visibility::Visible;
+                    sprite.color = tile_color(pos, fov);
                 }
             }
         }
-        return true;
     }
-    false
+    for (transform, mut vis) in sprite_query.iter_mut() {
+        *vis = if fov.is_visible(&SpritePosition::from_vec3(&transform.translation)) {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+pub fn camera_follow_system(
+    player_query: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+    mut camera_query: Query<&mut Transform, (With<MainCamera>, Without<Player>)>,
+    mut anchored_query: Query<
+        &mut Transform,
+        (With<ScreenAnchored>, Without<MainCamera>, Without<Player>),
+    >,
+) {
+    if let Some(player_transform) = player_query.iter().next() {
+        for mut camera_transform in camera_query.iter_mut() {
+            let dif_x = player_transform.translation.x - camera_transform.translation.x;
+            let dif_y = player_transform.translation.y - camera_transform.translation.y;
+            if dif_x == 0.0 && dif_y == 0.0 {
+                continue;
+            }
+            camera_transform.translation.x += dif_x;
+            camera_transform.translation.y += dif_y;
+            for mut transform in anchored_query.iter_mut() {
+                transform.translation.x += dif_x;
+                transform.translation.y += dif_y;
+            }
+        }
+    }
 }
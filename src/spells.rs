@@ -0,0 +1,295 @@
+use crate::base::*;
+use crate::menu::*;
+use crate::ui::*;
+use crate::world::*;
+use bevy::prelude::*;
+
+// the keys casting the known spells, in the order of the spells menu
+const SPELL_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+pub struct SpellsPlugin;
+
+impl Plugin for SpellsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CastSpellEvent>()
+            .add_event::<SpellCastEvent>()
+            .init_resource::<SpellTargeting>()
+            .add_systems((spell_keys, cooldown_system).in_set(OnUpdate(GameState::Running)))
+            .add_system(spells_menu_event.in_set(OnUpdate(GameState::Menu)))
+            // spells can be asked for from the menus as well as from the map
+            .add_system(cast_system.after(spell_keys).after(spells_menu_event));
+    }
+}
+
+/// Asks to cast a spell, from the menu, a key or the area logic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CastSpellEvent {
+    pub spell: String,
+    // the chosen target, if any
+    pub position: Option<SpritePosition>,
+}
+
+impl CastSpellEvent {
+    pub fn new<S: Into<String>>(spell: S) -> Self {
+        Self {
+            spell: spell.into(),
+            position: None,
+        }
+    }
+
+    pub fn at<S: Into<String>>(spell: S, position: SpritePosition) -> Self {
+        Self {
+            spell: spell.into(),
+            position: Some(position),
+        }
+    }
+}
+
+/// A spell was cast, for the area logic to react to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpellCastEvent {
+    pub spell: String,
+    pub position: SpritePosition,
+    // the character on the target position
+    pub character: Option<String>,
+}
+
+/// The spell waiting for the player to click on its target tile
+#[derive(Debug, Default, Clone, Resource)]
+pub struct SpellTargeting(pub Option<String>);
+
+/// What happens when trying to cast a spell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CastOutcome {
+    Cast(SpellCastEvent),
+    // the player needs to pick the tile first
+    NeedsTile,
+    Unknown,
+    // the time left before the spell can be cast again, in milliseconds
+    Cooling(u64),
+    NoTarget,
+    OutOfReach,
+}
+
+impl CastOutcome {
+    /// What to tell the player when the spell is not cast straight away
+    pub fn text(&self) -> Option<String> {
+        match self {
+            CastOutcome::Cast(_) => None,
+            CastOutcome::NeedsTile => Some("Click where to cast the spell.".to_owned()),
+            CastOutcome::Unknown => Some("You don't know this spell.".to_owned()),
+            CastOutcome::Cooling(left) => Some(format!(
+                "You need to rest {}s before casting this spell again.",
+                left.div_ceil(1000)
+            )),
+            CastOutcome::NoTarget => Some("There is nobody close enough.".to_owned()),
+            CastOutcome::OutOfReach => Some("You cannot cast the spell there.".to_owned()),
+        }
+    }
+}
+
+/// Find the target of a spell, checking the player can cast it
+pub fn resolve_cast(
+    spells: &Spells,
+    area: &Area,
+    state: &AntheaState,
+    request: &CastSpellEvent,
+) -> CastOutcome {
+    let Some(spell) = spells.spell(&request.spell) else {
+        return CastOutcome::Unknown;
+    };
+    let left = spells.cooldown(&spell.name);
+    if left > 0 {
+        return CastOutcome::Cooling(left);
+    }
+    let player = &state.map_position;
+    let cast = |position: &SpritePosition| {
+        CastOutcome::Cast(SpellCastEvent {
+            spell: spell.name.clone(),
+            position: position.clone(),
+            character: area
+                .character_from_position(position)
+                .map(|c| c.name.clone()),
+        })
+    };
+    match (spell.target, &request.position) {
+        (SpellTarget::Player, _) => cast(player),
+        (SpellTarget::Tile, None) => CastOutcome::NeedsTile,
+        (SpellTarget::Tile, Some(p)) => {
+            if state.revealed.contains(p) && p.distance(player) <= VISIBILITY_DISTANCE as u32 {
+                cast(p)
+            } else {
+                CastOutcome::OutOfReach
+            }
+        }
+        (SpellTarget::Character, Some(p)) => {
+            if player.is_adjacent(p) && area.character_from_position(p).is_some() {
+                cast(p)
+            } else {
+                CastOutcome::NoTarget
+            }
+        }
+        (SpellTarget::Character, None) => area
+            .characters
+            .keys()
+            .filter(|p| player.is_adjacent(p))
+            .min()
+            .map(cast)
+            .unwrap_or(CastOutcome::NoTarget),
+    }
+}
+
+fn spell_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    spells: Res<Spells>,
+    mut cast: EventWriter<CastSpellEvent>,
+) {
+    for (key, spell) in SPELL_KEYS.iter().zip(spells.spells.iter()) {
+        if keyboard_input.just_pressed(*key) {
+            cast.send(CastSpellEvent::new(&spell.name));
+        }
+    }
+}
+
+fn spells_menu_event(
+    mut event_reader: EventReader<MenuItemEvent>,
+    mut close_menu: EventWriter<CloseMenuEvent>,
+    mut cast: EventWriter<CastSpellEvent>,
+) {
+    if let Some(e) = event_reader
+        .iter()
+        .find(|e| e.menu == SPELLS && !e.item.is_empty())
+    {
+        close_menu.send(CloseMenuEvent);
+        cast.send(CastSpellEvent::new(&e.item));
+    }
+}
+
+fn cast_system(
+    area: Res<Area>,
+    state: Res<AntheaState>,
+    mut spells: ResMut<Spells>,
+    mut targeting: ResMut<SpellTargeting>,
+    mut event_reader: EventReader<CastSpellEvent>,
+    mut cast: EventWriter<SpellCastEvent>,
+    mut queue: EventWriter<MessageEvent>,
+) {
+    for e in event_reader.iter() {
+        let outcome = resolve_cast(&spells, &area, &state, e);
+        if let Some(text) = outcome.text() {
+            queue.send(MessageEvent::new(text, MessageStyle::Info));
+        }
+        match outcome {
+            CastOutcome::Cast(c) => {
+                spells.start_cooldown(&c.spell);
+                cast.send(c);
+            }
+            CastOutcome::NeedsTile => targeting.0 = Some(e.spell.clone()),
+            _ => (),
+        }
+    }
+}
+
+fn cooldown_system(time: Res<Time>, mut spells: ResMut<Spells>) {
+    if !spells.cooldowns.is_empty() {
+        spells.tick(time.delta().as_millis() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Spells, Area, AntheaState) {
+        let mut spells = Spells::default();
+        spells
+            .add_spell(Spell::new("light", "Light"))
+            .add_spell(
+                Spell::new("cat", "Cat")
+                    .with_target(SpellTarget::Character)
+                    .with_cooldown(2000),
+            )
+            .add_spell(Spell::new("fire", "Fire").with_target(SpellTarget::Tile));
+        let mut area = Area::new("test", 0, SpritePosition::new(1, 1));
+        area.add_character(Character::new("rats", "Rats", "", 3, 3));
+        let mut state = AntheaState {
+            map_position: SpritePosition::new(2, 2),
+            ..Default::default()
+        };
+        state.revealed.insert(SpritePosition::new(4, 2));
+        (spells, area, state)
+    }
+
+    #[test]
+    fn test_update_spell() {
+        // saved before spells had targets and cooldowns
+        let mut spells: Spells =
+            ron::from_str(r#"(spells: [(name: "cat", description: "Cat")])"#).unwrap();
+        assert_eq!(SpellTarget::Player, spells.spell("cat").unwrap().target);
+        let cat = Spell::new("cat", "Cat")
+            .with_target(SpellTarget::Character)
+            .with_cooldown(2000);
+        spells
+            .update_spell(cat)
+            .update_spell(Spell::new("fire", "Fire"));
+        assert_eq!(SpellTarget::Character, spells.spell("cat").unwrap().target);
+        assert_eq!(2000, spells.spell("cat").unwrap().cooldown);
+        assert!(!spells.contains_spell("fire"));
+    }
+
+    #[test]
+    fn test_targets() {
+        let (spells, area, state) = setup();
+        let resolve = |e: CastSpellEvent| resolve_cast(&spells, &area, &state, &e);
+        let CastOutcome::Cast(light) = resolve(CastSpellEvent::new("light")) else {
+            panic!("light not cast");
+        };
+        assert_eq!(SpritePosition::new(2, 2), light.position);
+        let CastOutcome::Cast(cat) = resolve(CastSpellEvent::new("cat")) else {
+            panic!("cat not cast");
+        };
+        assert_eq!(Some("rats".to_string()), cat.character);
+        assert_eq!(
+            CastOutcome::NoTarget,
+            resolve(CastSpellEvent::at("cat", SpritePosition::new(1, 1)))
+        );
+        assert_eq!(CastOutcome::NeedsTile, resolve(CastSpellEvent::new("fire")));
+        assert!(matches!(
+            resolve(CastSpellEvent::at("fire", SpritePosition::new(4, 2))),
+            CastOutcome::Cast(_)
+        ));
+        // not seen yet
+        assert_eq!(
+            CastOutcome::OutOfReach,
+            resolve(CastSpellEvent::at("fire", SpritePosition::new(2, 4)))
+        );
+        assert_eq!(CastOutcome::Unknown, resolve(CastSpellEvent::new("dragon")));
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let (mut spells, area, state) = setup();
+        spells.start_cooldown("cat").start_cooldown("light");
+        assert_eq!(0, spells.cooldown("light"));
+        let outcome = resolve_cast(&spells, &area, &state, &CastSpellEvent::new("cat"));
+        assert_eq!(CastOutcome::Cooling(2000), outcome);
+        assert_eq!(
+            Some("You need to rest 2s before casting this spell again.".to_string()),
+            outcome.text()
+        );
+        spells.tick(1500);
+        assert_eq!(500, spells.cooldown("cat"));
+        spells.tick(1000);
+        assert!(spells.cooldowns.is_empty());
+    }
+}
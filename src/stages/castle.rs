@@ -1,28 +1,38 @@
 use crate::base::*;
+use crate::checks::*;
+use crate::conversation::*;
+use crate::effects::*;
+use crate::experience::*;
+use crate::items::*;
 use crate::menu::*;
+use crate::spells::*;
 use crate::ui::*;
 use crate::world::*;
 use bevy::prelude::*;
+use std::collections::HashMap;
 
 pub struct CastlePlugin;
 
 impl Plugin for CastlePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(castle_area())
+        app.insert_resource(AreaDefinition(castle_area))
+            .insert_resource(castle_area())
+            .insert_resource(Conversations(castle_conversations()))
             .add_system(affordance_mirror)
             .add_system(affordance_fountain)
             .add_system(action_fountain)
             .add_system(action_mirror)
-            .add_system(character_peleus)
             .add_system(character_nerita)
             .add_system(action_nerita)
+            .add_system(use_scissors)
             .add_system(character_cretien)
             .add_system(character_scopas)
-            .add_system(character_cherise)
             .add_system(character_rats)
             .add_system(action_rats)
+            .add_system(spell_cat)
+            .add_system(update_cat_spell.in_schedule(OnExit(GameState::Load)))
             .add_system(character_theon)
-            .add_system(consume_sword)
+            .add_system(equip_sword)
             .add_system(affordance_outside);
     }
 }
@@ -40,11 +50,14 @@ const SCARE: &str = "scare";
 
 const HAIR_CUT: &str = "hair_cut";
 const HAIR_CUT_SELF: &str = "hair_cut_self";
+const HAIR_CUT_NERITA: &str = "hair_cut_nerita";
+const HAIR_FIXED: &str = "hair_fixed";
 const PELEUS_FORBIDDEN: &str = "peleus_forbidden";
 const ALLOWED_TO_LEAVE: &str = "allowed_to_leave";
 const OPENED_EXIT: &str = "opened_exit";
 const TRAINED_BY_SCOPAS: &str = "trained_by_scopas";
 const OBTAINED_FOOD: &str = "obtained_food";
+const SWORD_EQUIPPED: &str = "sword_equipped";
 
 const PELEUS: &str = "Peleus";
 const NERITA: &str = "Nerita";
@@ -56,23 +69,33 @@ const THEON: &str = "Theon";
 const RATS: &str = "Rats";
 
 const CAT: &str = "cat";
+const CAT_SPRITE: &str = "sprites/effects/cat.png";
+// how long the cat illusion takes to cast again, in milliseconds
+const CAT_COOLDOWN: u64 = 10_000;
 
 const QUEST_RATS: &str = "Rats";
+// how good with a weapon one needs to be to have even odds against the rats
+const RATS_DIFFICULTY: u32 = 2;
 const RATS_GONE: &str = "rats_gone";
+const RATS_KILLED: &str = "rats_killed";
+const RATS_SCARED: &str = "rats_scared";
+const RATS_BITTEN: &str = "rats_bitten";
 
 fn castle_area() -> Area {
     let mut stage = Area::new("Selaion Palace", 0, SpritePosition::new(20, 4));
     let bedroom = Room::new("bedroom", "Your bedroom", 6, 3, 9, 6);
     let throne = Room::new("throne", "Selaion throne room", 11, 2, 26, 6);
-    let garden =
-        Room::new("garden", "The royal garden", 7, 8, 15, 12).add_dimensions(16, 9, 16, 11);
+    let garden = Room::new("garden", "The royal garden", 7, 8, 15, 12)
+        .add_dimensions(16, 9, 16, 11)
+        .with_visibility(8);
     let study = Room::new("study", "The study", 28, 2, 32, 5);
     let courtyard = Room::new("courtyard", "The courtyard", 17, 8, 25, 28)
         .add_dimensions(26, 8, 26, 26)
-        .add_dimensions(27, 19, 35, 26);
+        .add_dimensions(27, 19, 35, 26)
+        .with_visibility(6);
     let kitchen = Room::new("kitchen", "The kitchen", 9, 19, 15, 24);
-    let cellar = Room::new("cellar", "The cellar", 2, 20, 4, 24);
-    let corridor = Room::new("corridor", "A dark corridor", 5, 22, 8, 22);
+    let cellar = Room::new("cellar", "The cellar", 2, 20, 4, 24).with_visibility(3);
+    let corridor = Room::new("corridor", "A dark corridor", 5, 22, 8, 22).with_visibility(2);
     let armory = Room::new("armory", "The armory", 31, 15, 35, 17);
     let gates = Room::new("gates", "The palace gates", 20, 29, 22, 29);
 
@@ -98,7 +121,9 @@ fn castle_area() -> Area {
         "sprites/items/double_sword.png",
         14,
         12,
-    );
+    )
+    .with_details("Sharpish scissors, good enough to cut hair if you find a mirror.")
+    .usable();
     stage.add_item(scissors);
     let scroll = Item::new(
         SCROLL,
@@ -106,15 +131,17 @@ fn castle_area() -> Area {
         "sprites/items/scroll-brown.png",
         4,
         20,
-    );
+    )
+    .with_details("The scroll is covered in strange symbols you cannot read.");
     stage.add_item(scroll);
-    let sword = Item::new_consumable(
+    let sword = Item::new(
         SWORD,
         "Small sword",
         "sprites/items/long_sword1.png",
         34,
         15,
-    );
+    )
+    .with_details("A small sword, light enough for you to wield.");
     stage.add_item(sword);
 
     let peleus = Character::new(
@@ -130,14 +157,16 @@ fn castle_area() -> Area {
         "sprites/people/nerita.png",
         6,
         4,
-    );
+    )
+    .with_behaviour(Behaviour::Wander("bedroom".into()));
     let cretien = Character::new(
         CRETIEN,
         "Cretien, your old teacher",
         "sprites/people/cretien.png",
         30,
         5,
-    );
+    )
+    .with_behaviour(Behaviour::Wander("study".into()));
     let scopas = Character::new(
         SCOPAS,
         "Scopas, the weapons master",
@@ -151,16 +180,26 @@ fn castle_area() -> Area {
         "sprites/people/cherise.png",
         12,
         21,
-    );
+    )
+    // in the kitchen by day, taking the air in the garden at night
+    .with_behaviour(Behaviour::Schedule(vec![
+        (6, SpritePosition::new(12, 21)),
+        (21, SpritePosition::new(10, 9)),
+    ]));
     let theon = Character::new(
         THEON,
         "Theon, a palace guard",
         "sprites/people/theon.png",
         21,
         27,
-    );
+    )
+    .with_behaviour(Behaviour::Patrol(vec![
+        SpritePosition::new(19, 27),
+        SpritePosition::new(23, 27),
+    ]));
 
-    let rats = Character::new(RATS, "Big rats", "sprites/people/rat.png", 2, 24);
+    let rats = Character::new(RATS, "Big rats", "sprites/people/rat.png", 2, 24)
+        .with_kind(CharacterKind::Animal);
 
     stage
         .add_character(peleus)
@@ -174,6 +213,27 @@ fn castle_area() -> Area {
     stage
 }
 
+fn mirror_menu() -> Menu {
+    let mi = MenuItem::new(CUT, "Cut your hair with the scissors?");
+    Menu::new(MIRROR, "Mirror", vec![mi])
+}
+
+fn fountain_menu() -> Menu {
+    let mi = MenuItem::new(
+        CUT,
+        "Cut your hair with the scissors, using the fountain as a mirror?",
+    );
+    Menu::new(FOUNTAIN, "Fountain", vec![mi])
+}
+
+fn nerita_scissors_menu() -> Menu {
+    let mi = MenuItem::new(
+        CUT,
+        "You really want me to cut your hair with these scissors?",
+    );
+    Menu::new(NERITA, "Nerita, your maid", vec![mi])
+}
+
 fn affordance_mirror(
     inventory: Res<Inventory>,
     flags: Res<QuestFlags>,
@@ -183,9 +243,7 @@ fn affordance_mirror(
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == MIRROR) {
         if inventory.contains_item(SCISSORS) {
-            let mi = MenuItem::new(CUT, "Cut your hair with the scissors?");
-            let m = Menu::new(MIRROR, "Mirror", vec![mi]);
-            menu.send(MenuEvent::new(m));
+            menu.send(MenuEvent::new(mirror_menu()));
         } else if flags.has_flag(QUEST_MAIN, HAIR_CUT) {
             queue.send(MessageEvent::new(
                 "Your look at yourself and your short hair...",
@@ -209,12 +267,7 @@ fn affordance_fountain(
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == FOUNTAIN) {
         if inventory.contains_item(SCISSORS) {
-            let mi = MenuItem::new(
-                CUT,
-                "Cut your hair with the scissors, using the fountain as a mirror?",
-            );
-            let m = Menu::new(FOUNTAIN, "Fountain", vec![mi]);
-            menu.send(MenuEvent::new(m));
+            menu.send(MenuEvent::new(fountain_menu()));
         } else if flags.has_flag(QUEST_MAIN, HAIR_CUT) {
             queue.send(MessageEvent::new(
                 "Your reflection in the water looks like a grinning boy...",
@@ -232,7 +285,7 @@ fn affordance_fountain(
 fn action_fountain(
     mut event_reader: EventReader<MenuItemEvent>,
     mut inventory: ResMut<Inventory>,
-    mut talents: ResMut<Talents>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut queue: EventWriter<MessageEvent>,
     mut journal: EventWriter<JournalEvent>,
     mut flags: ResMut<QuestFlags>,
@@ -244,7 +297,7 @@ fn action_fountain(
         .find(|e| e.menu == FOUNTAIN && e.item == CUT)
     {
         inventory.remove_item(SCISSORS);
-        talents.people += 1;
+        talent_events.send(TalentEvent::Raise(PEOPLE.into(), 1));
         close_menu.send(CloseMenuEvent);
         journal.send(JournalEvent::new(
             QUEST_MAIN,
@@ -259,7 +312,7 @@ fn action_fountain(
         ));
 
         queue.send(MessageEvent::new(
-            "You feel you've made a mess, but you cut your hair short (People +1).",
+            "You feel you've made a mess, but you cut your hair short.",
             MessageStyle::Info,
         ));
     }
@@ -268,7 +321,7 @@ fn action_fountain(
 fn action_mirror(
     mut event_reader: EventReader<MenuItemEvent>,
     mut inventory: ResMut<Inventory>,
-    mut talents: ResMut<Talents>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut queue: EventWriter<MessageEvent>,
     mut journal: EventWriter<JournalEvent>,
     mut flags: ResMut<QuestFlags>,
@@ -280,7 +333,7 @@ fn action_mirror(
         .find(|e| e.menu == MIRROR && e.item == CUT)
     {
         inventory.remove_item(SCISSORS);
-        talents.people += 2;
+        talent_events.send(TalentEvent::Raise(PEOPLE.into(), 2));
         body_change.send(BodyChangeEvent::new(
             PlayerPart::Hair,
             "sprites/people/hair_short.png",
@@ -292,57 +345,181 @@ fn action_mirror(
         ));
         flags.set_flag(QUEST_MAIN, HAIR_CUT);
         queue.send(MessageEvent::new(
-            "You carefully cut your hair short (People +2).",
+            "You carefully cut your hair short.",
             MessageStyle::Info,
         ));
     }
 }
 
-fn character_peleus(
-    mut flags: ResMut<QuestFlags>,
-    mut event_reader: EventReader<CharacterEvent>,
-    mut queue: EventWriter<MessageEvent>,
-    mut journal: EventWriter<JournalEvent>,
-) {
-    for _e in event_reader.iter().filter(|e| e.0 == PELEUS) {
-        if flags.has_flag(QUEST_MAIN, HAIR_CUT) {
-            if flags.has_flag(QUEST_MAIN, ALLOWED_TO_LEAVE) {
-                queue.send(MessageEvent::new(
-                    "You haven't left yet?",
-                    MessageStyle::Info,
-                ));
-            } else {
-                flags.set_flag(QUEST_MAIN, ALLOWED_TO_LEAVE);
-                queue.send(MessageEvent::new(
-                    "I see you're determined enough get rid of the hair you were so proud of.\nAllright, I will give orders that you're allowed to leave.",
-                    MessageStyle::Info,
-                ));
+fn flag(quest: &str, flag: &str) -> Requirement {
+    Requirement::Flag(quest.to_owned(), flag.to_owned())
+}
 
-                journal.send(JournalEvent::new(
-                    QUEST_MAIN,
-                    "Peleus has allowed me to leave on my quest for Father!",
-                ));
-            }
-        } else if flags.has_flag(QUEST_MAIN, PELEUS_FORBIDDEN) {
-            queue.send(MessageEvent::new(
-                "Once again, I am NOT going to let a girl go chasing a ghost.\nYour duty is to stay here and marry to strenghten my kingdom.\nDon't insist!",
-                MessageStyle::Info,
-            ));
-        } else {
-            flags.set_flag(QUEST_MAIN, PELEUS_FORBIDDEN);
-            journal.send(JournalEvent::new(
+fn set_flag(quest: &str, flag: &str) -> Effect {
+    Effect::SetFlag(quest.to_owned(), flag.to_owned())
+}
+
+fn journal_entry(quest: &str, text: &str) -> Effect {
+    Effect::Journal(quest.to_owned(), text.to_owned())
+}
+
+fn peleus_conversation() -> Conversation {
+    let mut c = Conversation::new("Peleus, your brother");
+    c.add_opening(vec![flag(QUEST_MAIN, ALLOWED_TO_LEAVE)], "waiting")
+        .add_opening(vec![flag(QUEST_MAIN, HAIR_CUT)], "allowed")
+        .add_opening(vec![flag(QUEST_MAIN, PELEUS_FORBIDDEN)], "again")
+        .add_opening(vec![], "forbid")
+        .add_node(
+            "waiting",
+            ConversationNode::new(
+                "You haven't left yet?",
+                vec![Response::new("I'm going.", None)],
+            ),
+        )
+        .add_node(
+            "allowed",
+            ConversationNode::new(
+                "I see you're determined enough get rid of the hair you were so proud of.\nAllright, I will give orders that you're allowed to leave.",
+                vec![Response::new("Thank you, brother.", None)],
+            )
+            .with_effect(set_flag(QUEST_MAIN, ALLOWED_TO_LEAVE))
+            .with_effect(journal_entry(
                 QUEST_MAIN,
-                "Peleus forbids me to leave. He'll see!",
-            ));
-            queue.send(MessageEvent::new(
+                "Peleus has allowed me to leave on my quest for Father!",
+            )),
+        )
+        .add_node(
+            "forbid",
+            ConversationNode::new(
                 "I am NOT going to let a girl go chasing a ghost.\nYour duty is to stay here and marry to strenghten my kingdom.",
-                MessageStyle::Info,
-            ));
-        }
-    }
+                vec![
+                    Response::new("Father may still be alive!", Some("ghost")),
+                    Response::new("Yes, brother.", None),
+                ],
+            )
+            .with_effect(set_flag(QUEST_MAIN, PELEUS_FORBIDDEN))
+            .with_effect(journal_entry(
+                QUEST_MAIN,
+                "Peleus forbids me to leave. He'll see!",
+            )),
+        )
+        .add_node(
+            "again",
+            ConversationNode::new(
+                "Once again, I am NOT going to let a girl go chasing a ghost.\nYour duty is to stay here and marry to strenghten my kingdom.\nDon't insist!",
+                vec![
+                    Response::new("Father may still be alive!", Some("ghost")),
+                    Response::new("Fine.", None),
+                ],
+            ),
+        )
+        .add_node(
+            "ghost",
+            ConversationNode::new(
+                "Father is dead, Anthea. The sooner you accept it, the better.",
+                vec![
+                    Response::new("You don't know that. Why can't I look for him?", Some("girl"))
+                        .with_requirement(Requirement::Talent(PEOPLE.into(), 1)),
+                    Response::new("...", None),
+                ],
+            ),
+        )
+        .add_node(
+            "girl",
+            ConversationNode::new(
+                "Because nobody on the roads would respect a girl with such long hair.\nThey would laugh at you, or worse.",
+                vec![Response::new("We'll see about that.", None)],
+            ),
+        );
+    c
+}
+
+fn cherise_conversation() -> Conversation {
+    let mut c = Conversation::new("Cherise, the cook");
+    c.add_opening(vec![flag(QUEST_RATS, QUEST_COMPLETED)], "thanks")
+        .add_opening(vec![flag(QUEST_RATS, RATS_GONE)], "reward")
+        .add_opening(vec![flag(QUEST_RATS, QUEST_STARTED)], "crazy")
+        .add_opening(vec![], "rats")
+        .add_node(
+            "thanks",
+            ConversationNode::new(
+                "Thanks again for getting rid of these rats!",
+                vec![Response::new("You're welcome.", None)],
+            ),
+        )
+        .add_node(
+            "reward",
+            ConversationNode::new(
+                "You got rid of the rats? Great! Here's some food for you...",
+                vec![
+                    Response::new("Could you spare a bit more? The road is long.", Some("more"))
+                        .with_requirement(Requirement::Talent(PEOPLE.into(), 2)),
+                    Response::new("Thank you!", None),
+                ],
+            )
+            .with_effect(set_flag(QUEST_RATS, QUEST_COMPLETED))
+            .with_effect(set_flag(QUEST_MAIN, OBTAINED_FOOD))
+            .with_effect(journal_entry(
+                QUEST_MAIN,
+                "Cherise gave me some food to thank me for getting rid of the rats in the cellar",
+            )),
+        )
+        .add_node(
+            "more",
+            ConversationNode::new(
+                "Ha! You have your mother's smile. Here, take some cheese too, and don't tell your brother.",
+                vec![Response::new("I won't!", None)],
+            )
+            .with_effect(journal_entry(
+                QUEST_MAIN,
+                "Cherise slipped me some cheese as well. My brother must not know!",
+            )),
+        )
+        .add_node(
+            "crazy",
+            ConversationNode::new(
+                "These rats are driving me crazy!",
+                vec![Response::new("I'll deal with them.", None)],
+            ),
+        )
+        .add_node(
+            "rats",
+            ConversationNode::new(
+                "Don't tell your brother, but there are rats in the cellar. I can't get rid of them!",
+                vec![
+                    Response::new("I could help.", Some("help")),
+                    Response::new("Rats? Ugh.", None),
+                ],
+            ),
+        )
+        .add_node(
+            "help",
+            ConversationNode::new(
+                "Really? I wish somebody would kill them all! The cellar is past the dark corridor.",
+                vec![Response::new("Leave it to me.", None)],
+            )
+            .with_effect(Effect::Quest(
+                QUEST_RATS.to_owned(),
+                "Get rid of the rats in the cellar".to_owned(),
+            ))
+            .with_effect(set_flag(QUEST_RATS, QUEST_STARTED))
+            .with_effect(journal_entry(
+                QUEST_RATS,
+                "Cherise would like somebody to kill the rats in the cellar.",
+            )),
+        );
+    c
+}
+
+fn castle_conversations() -> HashMap<String, Conversation> {
+    let mut conversations = HashMap::new();
+    conversations.insert(PELEUS.to_owned(), peleus_conversation());
+    conversations.insert(CHERISE.to_owned(), cherise_conversation());
+    conversations
 }
 
 fn character_nerita(
+    area: Res<Area>,
     flags: Res<QuestFlags>,
     inventory: Res<Inventory>,
     mut event_reader: EventReader<CharacterEvent>,
@@ -351,12 +528,7 @@ fn character_nerita(
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == NERITA) {
         if inventory.contains_item(SCISSORS) {
-            let mi = MenuItem::new(
-                CUT,
-                "You really want me to cut your hair with these scissors?",
-            );
-            let m = Menu::new(NERITA, "Nerita, your maid", vec![mi]);
-            menu.send(MenuEvent::new(m));
+            menu.send(MenuEvent::new(nerita_scissors_menu()));
         } else if flags.has_flag(QUEST_MAIN, HAIR_CUT) {
             if flags.has_flag(QUEST_MAIN, HAIR_CUT_SELF) {
                 let mi = MenuItem::new(
@@ -366,24 +538,56 @@ fn character_nerita(
                 let m = Menu::new(NERITA, "Nerita, your maid", vec![mi]);
                 menu.send(MenuEvent::new(m));
             } else {
-                queue.send(MessageEvent::new(
-                    "You look like a boy now! A pretty boy!",
-                    MessageStyle::Info,
-                ));
+                queue.send(say(&area, NERITA, "You look like a boy now! A pretty boy!"));
             }
         } else {
-            queue.send(MessageEvent::new(
+            queue.send(say(
+                &area,
+                NERITA,
                 "You'll always be a little girl to me. Let me comb your hair!",
-                MessageStyle::Info,
             ));
         }
     }
 }
 
+/// The scissors can be used from the inventory, on the mirror, the fountain or Nerita
+fn use_scissors(
+    area: Res<Area>,
+    state: Res<AntheaState>,
+    mut event_reader: EventReader<UseItemEvent>,
+    mut queue: EventWriter<MessageEvent>,
+    mut menu: EventWriter<MenuEvent>,
+    mut close_menu: EventWriter<CloseMenuEvent>,
+) {
+    for e in event_reader.iter().filter(|e| e.item == SCISSORS) {
+        let target = match &e.action {
+            ItemAction::Use => targets_near(&area, &state.map_position)
+                .into_iter()
+                .map(|(name, _)| name)
+                .find(|name| name == MIRROR || name == FOUNTAIN),
+            ItemAction::UseOn(target) => Some(target.clone()),
+            _ => continue,
+        };
+        match target.as_deref() {
+            Some(MIRROR) => menu.send(MenuEvent::new(mirror_menu())),
+            Some(FOUNTAIN) => menu.send(MenuEvent::new(fountain_menu())),
+            Some(NERITA) => menu.send(MenuEvent::new(nerita_scissors_menu())),
+            _ => {
+                close_menu.send(CloseMenuEvent);
+                queue.send(MessageEvent::new(
+                    "You'd need a mirror to cut your hair.",
+                    MessageStyle::Info,
+                ));
+            }
+        }
+    }
+}
+
 fn action_nerita(
+    area: Res<Area>,
     mut event_reader: EventReader<MenuItemEvent>,
     mut inventory: ResMut<Inventory>,
-    mut talents: ResMut<Talents>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut queue: EventWriter<MessageEvent>,
     mut journal: EventWriter<JournalEvent>,
     mut flags: ResMut<QuestFlags>,
@@ -393,7 +597,7 @@ fn action_nerita(
     if let Some(e) = event_reader.iter().find(|e| e.menu == NERITA) {
         if e.item == CUT {
             inventory.remove_item(SCISSORS);
-            talents.people += 2;
+            talent_events.send(TalentEvent::Raise(PEOPLE.into(), 2));
             body_change.send(BodyChangeEvent::new(
                 PlayerPart::Hair,
                 "sprites/people/hair_short.png",
@@ -404,27 +608,28 @@ fn action_nerita(
                 "Nerita cut my hair so I don't look too much like a girl now. I think it suits me.",
             ));
             flags.set_flag(QUEST_MAIN, HAIR_CUT);
-            queue.send(MessageEvent::new(
-                "Really a shame to cut such beautiful hair (People +2)!",
-                MessageStyle::Info,
+            flags.set_flag(QUEST_MAIN, HAIR_CUT_NERITA);
+            queue.send(say(
+                &area,
+                NERITA,
+                "Really a shame to cut such beautiful hair!",
             ));
         } else if e.item == FIX {
-            talents.people += 1;
+            talent_events.send(TalentEvent::Raise(PEOPLE.into(), 1));
             close_menu.send(CloseMenuEvent);
             journal.send(JournalEvent::new(
                 QUEST_MAIN,
                 "Nerita fixed my hair so it doesn't look as bad as it used to.",
             ));
             flags.unset_flag(QUEST_MAIN, HAIR_CUT_SELF);
-            queue.send(MessageEvent::new(
-                "Now, you look a bit better now (People +1)!",
-                MessageStyle::Info,
-            ));
+            flags.set_flag(QUEST_MAIN, HAIR_FIXED);
+            queue.send(say(&area, NERITA, "Now, you look a bit better now!"));
         }
     }
 }
 
 fn character_cretien(
+    area: Res<Area>,
     mut event_reader: EventReader<CharacterEvent>,
     mut queue: EventWriter<MessageEvent>,
     mut inventory: ResMut<Inventory>,
@@ -433,40 +638,40 @@ fn character_cretien(
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == CRETIEN) {
         if inventory.contains_item(SCROLL) {
-            queue.send(MessageEvent::new(
+            queue.send(say(
+                &area,
+                CRETIEN,
                 "Ooohh, this scroll is a magic spell! Let me see if I can teach you the incantation (Spell gained)...",
-                MessageStyle::Info,
             ));
             inventory.remove_item(SCROLL);
-            let spell = Spell::new(CAT, "Create the illusion of a cat!");
-            spells.add_spell(spell);
+            spells.add_spell(cat_spell());
             journal.send(JournalEvent::new(
                 QUEST_MAIN,
                 "Cretien taught me a little spell, not sure if it'll be useful...",
             ));
         } else {
-            queue.send(MessageEvent::new(
+            queue.send(say(
+                &area,
+                CRETIEN,
                 "I'm always on the lookout for new knowledge!",
-                MessageStyle::Info,
             ));
         }
     }
 }
 
 fn character_scopas(
+    area: Res<Area>,
     mut event_reader: EventReader<CharacterEvent>,
     mut queue: EventWriter<MessageEvent>,
-    mut talents: ResMut<Talents>,
+    talents: Res<Talents>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut flags: ResMut<QuestFlags>,
     mut journal: EventWriter<JournalEvent>,
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == SCOPAS) {
-        if talents.weapons > 0 {
+        if talents.value(WEAPONS) > 0 {
             if flags.has_flag(QUEST_MAIN, TRAINED_BY_SCOPAS) {
-                queue.send(MessageEvent::new(
-                    "Don't tire yourself out!",
-                    MessageStyle::Info,
-                ));
+                queue.send(say(&area, SCOPAS, "Don't tire yourself out!"));
             } else {
                 flags.set_flag(QUEST_MAIN, TRAINED_BY_SCOPAS);
                 journal.send(JournalEvent::new(
@@ -474,84 +679,47 @@ fn character_scopas(
                     "Scopas gave me a hard fighting lesson.",
                 ));
 
-                queue.send(MessageEvent::new(
-                    "You're getting better with a weapon, but you still need to practise (Weapons +1)!",
-                    MessageStyle::Info,
+                queue.send(say(
+                    &area,
+                    SCOPAS,
+                    "You're getting better with a weapon, but you still need to practise!",
                 ));
-                talents.weapons += 1;
+                talent_events.send(TalentEvent::Raise(WEAPONS.into(), 1));
             }
         } else {
-            queue.send(MessageEvent::new(
+            queue.send(say(
+                &area,
+                SCOPAS,
                 "Get a weapon and come back to me to train.",
-                MessageStyle::Info,
             ));
         }
     }
 }
 
-fn consume_sword(
+fn equip_sword(
     mut event_reader: EventReader<ItemEvent>,
-    mut talents: ResMut<Talents>,
-    mut body_change: EventWriter<BodyChangeEvent>,
-    mut queue: EventWriter<MessageEvent>,
-) {
-    for _e in event_reader.iter().filter(|e| e.0 == SWORD) {
-        body_change.send(BodyChangeEvent::new(
-            PlayerPart::RightHand,
-            "sprites/people/short_sword.png",
-        ));
-        talents.weapons += 1;
-        queue.send(MessageEvent::new(
-            "You now have a weapon (Weapons +1)!",
-            MessageStyle::Info,
-        ));
-    }
-}
-
-fn character_cherise(
-    mut event_reader: EventReader<CharacterEvent>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut queue: EventWriter<MessageEvent>,
     mut flags: ResMut<QuestFlags>,
-    mut journal: ResMut<Journal>,
-    mut journale: EventWriter<JournalEvent>,
 ) {
-    for _e in event_reader.iter().filter(|e| e.0 == CHERISE) {
-        if flags.has_flag(QUEST_RATS, QUEST_STARTED) {
-            if flags.has_flag(QUEST_RATS, QUEST_COMPLETED) {
-                queue.send(MessageEvent::new(
-                    "Thanks again for killing these rats!",
-                    MessageStyle::Info,
-                ));
-            } else if flags.has_flag(QUEST_RATS, RATS_GONE) {
-                flags.set_flag(QUEST_RATS, QUEST_COMPLETED);
-                journale.send(JournalEvent::new(QUEST_MAIN,"Cherise gave me some food to thank me for getting rid of the rats in the cellar"));
-                flags.set_flag(QUEST_MAIN, OBTAINED_FOOD);
-                queue.send(MessageEvent::new(
-                    "You got rid of the rats? Great! Here's some food for you...",
-                    MessageStyle::Info,
-                ));
-            } else {
-                queue.send(MessageEvent::new(
-                    "These rats are driving me crazy!",
-                    MessageStyle::Info,
-                ));
-            }
-        } else {
-            flags.set_flag(QUEST_RATS, QUEST_STARTED);
-            let q = Quest::new(QUEST_RATS, "Get rid of the rats in the cellar");
-            journal.add_quest(q);
-            journale.send(JournalEvent::new(
-                QUEST_RATS,
-                "Cherise would like somebody to kill the rats in the cellar.",
-            ));
+    for _e in event_reader.iter().filter(|e| e.0 == SWORD) {
+        // the sword can be dropped and picked up again, it only teaches something the first time
+        if !flags.has_flag(QUEST_MAIN, SWORD_EQUIPPED) {
+            flags.set_flag(QUEST_MAIN, SWORD_EQUIPPED);
+            talent_events.send(TalentEvent::Raise(WEAPONS.into(), 1));
             queue.send(MessageEvent::new(
-                "Don't tell your brother, but there are rats in the cellar. I can't get rid of them, I wish somebody would kill them all!",
+                "You now have a weapon!",
                 MessageStyle::Info,
             ));
         }
     }
 }
 
+/// The flag set when losing a fight against the rats, so that the next try is another day
+fn bitten_flag(clock: &GameClock) -> String {
+    format!("{}_{}", RATS_BITTEN, clock.day())
+}
+
 fn character_rats(
     mut event_reader: EventReader<CharacterEvent>,
     mut queue: EventWriter<MessageEvent>,
@@ -559,12 +727,22 @@ fn character_rats(
     talents: Res<Talents>,
     spells: Res<Spells>,
     flags: Res<QuestFlags>,
+    clock: Res<GameClock>,
 ) {
     for _e in event_reader.iter().filter(|e| e.0 == RATS) {
         if flags.has_flag(QUEST_RATS, QUEST_STARTED) {
             let mut mis = vec![];
-            if talents.weapons > 1 {
-                mis.push(MenuItem::new(FIGHT, "Kill the rats!"));
+            if flags.has_flag(QUEST_RATS, bitten_flag(&clock)) {
+                mis.push(MenuItem::new(
+                    "",
+                    "Your bites still hurt too much to fight them again today.",
+                ));
+            } else if talents.value(WEAPONS) > 0 {
+                let check = TalentCheck::new(WEAPONS, RATS_DIFFICULTY);
+                mis.push(MenuItem::new(
+                    FIGHT,
+                    check.label("Kill the rats!", &talents),
+                ));
             }
             if spells.contains_spell(CAT) {
                 mis.push(MenuItem::new(SCARE, "Create the illusion of a cat"));
@@ -591,44 +769,115 @@ fn character_rats(
     }
 }
 
+/// The rats leave the cellar for good
+fn rats_gone(
+    effects: &mut EventWriter<EffectEvent>,
+    flags: &mut QuestFlags,
+    area: &mut Area,
+    character_query: &Query<(Entity, &Character)>,
+) {
+    flags.set_flag(QUEST_RATS, RATS_GONE);
+    for (e, _i2) in character_query.iter().filter(|(_e, c)| c.name == RATS) {
+        effects.send(EffectEvent::FadeOut(e));
+    }
+    area.remove_character(RATS);
+}
+
+fn cat_spell() -> Spell {
+    Spell::new(CAT, "Create the illusion of a cat!")
+        .with_target(SpellTarget::Character)
+        .with_cooldown(CAT_COOLDOWN)
+}
+
+/// Games saved before spells had targets and cooldowns would cast the cat on the player
+fn update_cat_spell(mut spells: ResMut<Spells>) {
+    spells.update_spell(cat_spell());
+}
+
 fn action_rats(
-    mut commands: Commands,
     mut event_reader: EventReader<MenuItemEvent>,
     mut queue: EventWriter<MessageEvent>,
-    mut talents: ResMut<Talents>,
+    talents: Res<Talents>,
+    mut talent_events: EventWriter<TalentEvent>,
     mut flags: ResMut<QuestFlags>,
     mut area: ResMut<Area>,
+    mut rng: ResMut<GameRng>,
+    clock: Res<GameClock>,
     mut close_menu: EventWriter<CloseMenuEvent>,
+    mut cast: EventWriter<CastSpellEvent>,
+    mut effects: EventWriter<EffectEvent>,
     character_query: Query<(Entity, &Character)>,
 ) {
     if let Some(e) = event_reader.iter().find(|e| e.menu == RATS) {
-        let mut gone = false;
-        if e.item == FIGHT {
+        if e.item == FIGHT && !flags.has_flag(QUEST_RATS, bitten_flag(&clock)) {
+            let check = TalentCheck::new(WEAPONS, RATS_DIFFICULTY);
+            // win or lose, a fight teaches something
+            talent_events.send(TalentEvent::Experience(WEAPONS.into(), USE_EXPERIENCE));
+            if check.roll(&talents, &mut rng) {
+                queue.send(MessageEvent::new(
+                    "You massacre the rats.",
+                    MessageStyle::Info,
+                ));
+                flags.set_flag(QUEST_RATS, RATS_KILLED);
+                rats_gone(&mut effects, &mut flags, &mut area, &character_query);
+            } else {
+                queue.send(MessageEvent::new(
+                    "There are too many of them! Bitten all over, you have to retreat.",
+                    MessageStyle::Info,
+                ));
+                flags.set_flag(QUEST_RATS, bitten_flag(&clock));
+            }
+            close_menu.send(CloseMenuEvent);
+        } else if e.item == SCARE {
+            close_menu.send(CloseMenuEvent);
+            // on the rats, not on whoever else is around
+            if let Some(rats) = area.character_from_name(RATS) {
+                cast.send(CastSpellEvent::at(CAT, rats.position.clone()));
+            }
+        }
+    }
+}
+
+/// The cat illusion scares the rats away, and only surprises the others
+fn spell_cat(
+    mut event_reader: EventReader<SpellCastEvent>,
+    mut effects: EventWriter<EffectEvent>,
+    mut queue: EventWriter<MessageEvent>,
+    mut talent_events: EventWriter<TalentEvent>,
+    mut flags: ResMut<QuestFlags>,
+    mut area: ResMut<Area>,
+    character_query: Query<(Entity, &Character)>,
+) {
+    for e in event_reader.iter().filter(|e| e.spell == CAT) {
+        effects.send(EffectEvent::Illusion(e.position.clone(), CAT_SPRITE.into()));
+        if e.character.as_deref() == Some(RATS) && flags.has_flag(QUEST_RATS, QUEST_STARTED) {
+            talent_events.send(TalentEvent::Raise(ANIMALS.into(), 1));
             queue.send(MessageEvent::new(
-                "You massacre the rats.",
+                "You pronounce the incantation, a big cat appears, scaring the rats away.",
                 MessageStyle::Info,
             ));
-            gone = true;
-        } else if e.item == SCARE {
-            talents.animals += 1;
+            flags.set_flag(QUEST_RATS, RATS_SCARED);
+            rats_gone(&mut effects, &mut flags, &mut area, &character_query);
+        } else {
             queue.send(MessageEvent::new(
-                "You pronounce the incantation, a big cat appears, scaring the rats away (Animals+1).",
+                "You pronounce the incantation, a big cat appears for a moment, then fades away.",
                 MessageStyle::Info,
             ));
-            gone = true;
-        }
-        if gone {
-            flags.set_flag(QUEST_RATS, RATS_GONE);
-
-            close_menu.send(CloseMenuEvent);
-            for (e, _i2) in character_query.iter().filter(|(_e, c)| c.name == RATS) {
-                commands.entity(e).despawn_recursive();
-            }
-            area.characters.retain(|_, v| v.name == RATS);
         }
     }
 }
 
+/// The gate tiles to remove, leading to the outside world
+fn open_gate(area: &mut Area) -> Vec<SpritePosition> {
+    (20..=22)
+        .map(|x| {
+            let outside = Affordance::new(format!("{}_{}", OUTSIDE, x), "The outside world", x, 29);
+            area.add_affordance(outside);
+            SpritePosition::new(x, 29)
+        })
+        .collect()
+}
+
 fn character_theon(
     mut event_reader: EventReader<CharacterEvent>,
     mut queue: EventWriter<MessageEvent>,
@@ -652,19 +901,9 @@ fn character_theon(
                     MessageStyle::Info,
                 ));
 
-                let gate_pos = vec![
-                    SpritePosition::new(-20, 29),
-                    SpritePosition::new(-21, 29),
-                    SpritePosition::new(-22, 29),
-                ];
-                for pos in gate_pos.into_iter() {
+                for pos in open_gate(&mut area) {
                     remove_tile.send(RemoveTileEvent::new(pos, 1));
                 }
-                for x in 20..=22 {
-                    let outside1 =
-                        Affordance::new(format!("{}_{}", OUTSIDE, x), "The outside world", x, 29);
-                    area.add_affordance(outside1);
-                }
             }
         } else {
             queue.send(MessageEvent::new(
@@ -675,11 +914,35 @@ fn character_theon(
     }
 }
 
+/// What the player did in the castle, for the end screen
+fn castle_story(flags: &QuestFlags) -> Vec<String> {
+    let hair = if flags.has_flag(QUEST_MAIN, HAIR_CUT_NERITA) {
+        "Nerita cut your hair short."
+    } else if flags.has_flag(QUEST_MAIN, HAIR_FIXED) {
+        "You cut your hair at the fountain, and Nerita fixed it."
+    } else if flags.has_flag(QUEST_MAIN, HAIR_CUT_SELF) {
+        "You cut your hair at the fountain, and it shows."
+    } else if flags.has_flag(QUEST_MAIN, HAIR_CUT) {
+        "You cut your hair in front of your bedroom mirror."
+    } else {
+        "You kept your long hair."
+    };
+    let rats = if flags.has_flag(QUEST_RATS, RATS_SCARED) {
+        "You scared the rats away with a spell."
+    } else if flags.has_flag(QUEST_RATS, RATS_KILLED) {
+        "You killed the rats in the cellar."
+    } else {
+        "You left the rats in the cellar."
+    };
+    vec![hair.to_owned(), rats.to_owned()]
+}
+
 fn affordance_outside(
     talents: Res<Talents>,
     flags: Res<QuestFlags>,
     mut event_reader: EventReader<AffordanceEvent>,
     mut queue: EventWriter<MessageEvent>,
+    mut ending: ResMut<Ending>,
     mut state: ResMut<NextState<GameState>>,
 ) {
     for _e in event_reader.iter().filter(|e| e.0.starts_with(OUTSIDE)) {
@@ -688,23 +951,73 @@ fn affordance_outside(
                 "You should get food before venturing outside",
                 MessageStyle::Info,
             ));
-        } else if talents.weapons < 1 {
+        } else if talents.value(WEAPONS) < 1 {
             queue.send(MessageEvent::new(
                 "You should get a weapon, the outside world is not safe",
                 MessageStyle::Info,
             ));
         } else {
-            queue.send(MessageEvent::new_multi(vec![
-                Message {
-                    contents: "Success!".to_owned(),
-                    style: MessageStyle::Title,
-                },
-                Message {
-                    contents: "You pass the castle gate. Your adventure truly begins!".to_owned(),
-                    style: MessageStyle::Info,
-                },
-            ]));
+            *ending = Ending {
+                title: "Success!".to_owned(),
+                text: "You pass the castle gate. Your adventure truly begins!".to_owned(),
+                story: castle_story(&flags),
+            };
             state.set(GameState::End);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sword_weapons_once() {
+        let mut app = App::new();
+        app.add_event::<ItemEvent>()
+            .add_event::<TalentEvent>()
+            .add_event::<MessageEvent>()
+            .insert_resource(QuestFlags::default())
+            .insert_resource(Talents::default())
+            .insert_resource(Inventory::default())
+            .add_systems((
+                equip_sword,
+                (|mut talents: ResMut<Talents>, mut events: EventReader<TalentEvent>| {
+                    for e in events.iter() {
+                        apply_talent_event(&mut talents, e);
+                    }
+                })
+                .after(equip_sword),
+            ));
+        let sword = Item::new(SWORD, "Small sword", "", 0, 0);
+        let pick_up = |app: &mut App| {
+            let mut inventory = app.world.resource_mut::<Inventory>();
+            inventory
+                .add_item(sword.clone())
+                .equip(SWORD, PlayerPart::RightHand);
+            app.world.send_event(ItemEvent(SWORD.into()));
+            app.update();
+        };
+
+        pick_up(&mut app);
+        let weapons = app.world.resource::<Talents>().value(WEAPONS);
+        assert_eq!(1, weapons);
+        app.world.resource_mut::<Inventory>().remove_item(SWORD);
+        pick_up(&mut app);
+        assert_eq!(weapons, app.world.resource::<Talents>().value(WEAPONS));
+    }
+
+    #[test]
+    fn test_open_gate() {
+        let mut area = Area::new("test", 0, SpritePosition::new(0, 0));
+        let gate = open_gate(&mut area);
+        assert_eq!(3, gate.len());
+        // the removed tiles are the ones leading outside
+        for pos in gate {
+            assert!(area
+                .affordance_from_position(&pos)
+                .map(|a| a.name.starts_with(OUTSIDE))
+                .unwrap_or_default());
+        }
+    }
+}
@@ -172,6 +172,21 @@ pub fn is_tile_passable(path: &str) -> bool {
     true
 }
 
+/// How much walking over a tile costs, so that paths keep to the floors and alleys
+pub fn tile_cost(path: &str) -> u32 {
+    let img = path.split('/').next_back().unwrap();
+    if img.contains("flowers") {
+        return 3;
+    }
+    if img.contains("grass") {
+        return 2;
+    }
+    if img.contains("sand") {
+        return 2;
+    }
+    1
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,6 +201,13 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_tile_cost() {
+        assert_eq!(1, tile_cost("sprites/tiles/limestone0.png"));
+        assert_eq!(2, tile_cost("sprites/tiles/grass0.png"));
+        assert_eq!(3, tile_cost("sprites/tiles/grass_flowers_red1.png"));
+    }
+
     #[test]
     fn test_map1() -> Result<(), anyhow::Error> {
         let data = std::fs::read("assets/castle1.tmx")?;
@@ -1,10 +1,20 @@
 use bevy::{prelude::*, ui::FocusPolicy};
+use serde::{Deserialize, Serialize};
 
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::base::*;
 
+// time to show one more character of a message, in milliseconds
+pub const TYPEWRITER_DELAY: u64 = 25;
+// how many characters fit on a line of the message box
+pub const PAGE_WIDTH: usize = 48;
+// how many lines fit in the message box
+pub const PAGE_LINES: usize = 4;
+// shown at the end of a page when there are more to read
+const MORE: &str = " >>";
+
 pub struct UIPlugin;
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, SystemSet)]
@@ -15,8 +25,17 @@ impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ClearMessage>()
             .add_event::<MessageEvent>()
+            .insert_resource(Dialogue::default())
             .add_system(setup_ui.in_schedule(OnEnter(GameState::Title)))
-            .add_systems((message_clear_system,message_system).chain())
+            .add_systems(
+                (
+                    message_clear_system,
+                    advance_dialogue,
+                    message_system,
+                    typewriter_system,
+                )
+                    .chain(),
+            )
             .configure_set(AfterPostUpdate.after(CoreSet::Update))
             .add_system(message_decoration_system.in_base_set(AfterPostUpdate))
             //.add_system(message_clear_system.in_base_set(CoreSet::PreUpdate));
@@ -24,7 +43,7 @@ impl Plugin for UIPlugin {
             ;
     }
 }
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum MessageStyle {
     Title,
     MenuTitle,
@@ -39,21 +58,50 @@ pub enum MessageStyle {
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct MessageEvent {
     pub messages: Vec<Message>,
+    pub speaker: Option<Speaker>,
 }
 
 impl MessageEvent {
     pub fn new<S: Into<String>>(msg: S, style: MessageStyle) -> Self {
         MessageEvent {
             messages: vec![Message::new(msg, style)],
+            speaker: None,
         }
     }
 
     pub fn new_multi(msgs: Vec<Message>) -> Self {
-        MessageEvent { messages: msgs }
+        MessageEvent {
+            messages: msgs,
+            speaker: None,
+        }
+    }
+
+    pub fn with_speaker<S1: Into<String>, S2: Into<String>>(
+        mut self,
+        name: S1,
+        sprite: S2,
+    ) -> Self {
+        self.speaker = Some(Speaker {
+            name: name.into(),
+            sprite: sprite.into(),
+        });
+        self
+    }
+
+    /// Plain text, that is shown page by page
+    pub fn is_dialogue(&self) -> bool {
+        !self.messages.is_empty() && self.messages.iter().all(|m| m.style == MessageStyle::Info)
     }
 }
 
+/// Who says a message, shown with their portrait
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Speaker {
+    pub name: String,
+    pub sprite: String,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Message {
     pub contents: String,
     pub style: MessageStyle,
@@ -71,6 +119,92 @@ impl Message {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct ClearMessage;
 
+/// The pages of the dialogue being shown, revealed character by character
+#[derive(Debug, Default, Clone, PartialEq, Resource)]
+pub struct Dialogue {
+    pub pages: Vec<String>,
+    pub page: usize,
+    // characters of the current page already shown
+    pub revealed: usize,
+    pub elapsed: u64,
+}
+
+impl Dialogue {
+    pub fn new(messages: &[Message]) -> Self {
+        Self {
+            pages: messages
+                .iter()
+                .flat_map(|m| paginate(&m.contents, PAGE_WIDTH, PAGE_LINES))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn current(&self) -> Option<&String> {
+        self.pages.get(self.page)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.current()
+            .map(|p| self.revealed >= p.chars().count())
+            .unwrap_or(true)
+    }
+
+    pub fn has_more(&self) -> bool {
+        self.page + 1 < self.pages.len()
+    }
+
+    /// Whether the player still has something to read
+    pub fn is_waiting(&self) -> bool {
+        !self.is_complete() || self.has_more()
+    }
+
+    /// Reveal more characters as time goes, returning whether there are new ones
+    pub fn tick(&mut self, delta: u64) -> bool {
+        if self.is_complete() {
+            return false;
+        }
+        self.elapsed += delta;
+        let revealed = (self.elapsed / TYPEWRITER_DELAY) as usize;
+        let changed = revealed > self.revealed;
+        self.revealed = revealed;
+        changed
+    }
+
+    /// Show the whole page, or go to the next one if it is already shown
+    pub fn advance(&mut self) -> bool {
+        if !self.is_complete() {
+            self.revealed = usize::MAX;
+        } else if self.has_more() {
+            self.page += 1;
+            self.revealed = 0;
+            self.elapsed = 0;
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+/// Wrap the text into lines of the given width, and group the lines into pages
+pub fn paginate(text: &str, width: usize, lines: usize) -> Vec<String> {
+    let mut all = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                all.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        all.push(line);
+    }
+    all.chunks(lines.max(1)).map(|c| c.join("\n")).collect()
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter, Component)]
 pub enum MessageFramePart {
     TopLeft,
@@ -100,6 +234,9 @@ pub struct InteractionItem(pub String);
 #[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
 pub struct TableItem;
 
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Component)]
+pub struct Portrait;
+
 const DIMENSIONS: &[((f32, f32), (f32, f32))] = &[
     // borders
     ((857.0, 192.0), (879.0, 212.0)),
@@ -121,7 +258,6 @@ pub fn setup_ui(
     mut commands: Commands,
     mut handles: ResMut<AntheaHandles>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut queue: EventWriter<MessageEvent>,
     mut state: ResMut<NextState<GameState>>,
 ) {
     let mut atlas = TextureAtlas::new_empty(handles.ui_handle.clone(), Vec2::new(1024.0, 666.0));
@@ -145,7 +281,8 @@ pub fn setup_ui(
             visibility: Visibility::Hidden,
             ..Default::default()
         })
-        .insert(Background);
+        .insert(Background)
+        .insert(ScreenAnchored);
 
     commands
         .spawn(NodeBundle {
@@ -179,20 +316,6 @@ pub fn setup_ui(
                 )
                 .insert(MessageText);
         });
-    queue.send(MessageEvent::new_multi(vec![
-        Message {
-            contents: "Anthea's Quest".to_owned(),
-            style: MessageStyle::Title,
-        },
-        Message {
-            contents: "You are Anthea, youngest daughter of the king of Selaion. Your father left some months ago to wage war and has never returned. Your brother Peleus now reigns on the throne. You have decided to leave in search of your Father to find out his fate.".to_owned(),
-            style: MessageStyle::Info,
-        },
-        Message {
-            contents: "Click to start".to_owned(),
-            style: MessageStyle::Help,
-        },
-    ]));
     state.set(GameState::Background);
 }
 
@@ -209,7 +332,8 @@ fn spawn_frame(
             visibility: Visibility::Hidden,
             ..Default::default()
         })
-        .insert(part);
+        .insert(part)
+        .insert(ScreenAnchored);
 }
 
 fn build_section(msg: &Message, font: Handle<Font>, sep: &str) -> TextSection {
@@ -230,8 +354,10 @@ fn build_section(msg: &Message, font: Handle<Font>, sep: &str) -> TextSection {
 
 fn message_system(
     handles: Res<AntheaHandles>,
+    asset_server: Res<AssetServer>,
     mut commands: Commands,
     mut event_reader: EventReader<MessageEvent>,
+    mut dialogue: ResMut<Dialogue>,
     mut text_query: Query<(&MessageText, &mut Text, &mut Style, &Parent)>,
     mut style_query: Query<&mut Style, Without<Text>>,
     msg_query: ParamSet<(
@@ -252,6 +378,11 @@ fn message_system(
             menu_query,
             table_query,
         );
+        *dialogue = if me.is_dialogue() {
+            Dialogue::new(&me.messages)
+        } else {
+            Dialogue::default()
+        };
 
         for (_mt, mut text, mut style, parent) in &mut text_query.iter_mut() {
             let mut ps = style_query.get_mut(parent.get()).unwrap();
@@ -259,7 +390,20 @@ fn message_system(
             style.align_self = Default::default();
             let mut sep = String::new();
             text.sections.clear();
-            
+            if let Some(speaker) = &me.speaker {
+                let portrait = build_portrait(&mut commands, &handles, &asset_server, speaker);
+                commands
+                    .entity(parent.get())
+                    .insert_children(0, &[portrait]);
+            }
+            if me.is_dialogue() {
+                text.sections = page_sections(&dialogue, handles.font_handle.clone());
+                if me.messages.len() == 1 && me.speaker.is_none() {
+                    ps.justify_content = JustifyContent::FlexEnd;
+                }
+                continue;
+            }
+
             commands.entity(parent.get()).with_children(|parent| {
                 let mut needs_close = false;
                 for msg in me.messages.iter() {
@@ -292,6 +436,102 @@ fn message_system(
     }
 }
 
+/// The text of the current page: what is not revealed yet is there but transparent,
+/// so that the box keeps the same size while the text appears
+fn page_sections(dialogue: &Dialogue, font: Handle<Font>) -> Vec<TextSection> {
+    let Some(page) = dialogue.current() else {
+        return vec![];
+    };
+    let split = page
+        .char_indices()
+        .nth(dialogue.revealed)
+        .map(|(i, _)| i)
+        .unwrap_or(page.len());
+    let section = |value: &str, color: Color| {
+        let mut ts = build_section(&Message::new(value, MessageStyle::Info), font.clone(), "");
+        ts.style.color = color;
+        ts
+    };
+    let more = if dialogue.has_more() && dialogue.is_complete() {
+        Color::BLACK
+    } else {
+        Color::NONE
+    };
+    vec![
+        section(&page[..split], Color::BLACK),
+        section(&page[split..], Color::NONE),
+        section(if dialogue.has_more() { MORE } else { "" }, more),
+    ]
+}
+
+/// The portrait and name of who is talking, laid out and cleared like a table row
+fn build_portrait(
+    commands: &mut Commands,
+    handles: &Res<AntheaHandles>,
+    asset_server: &Res<AssetServer>,
+    speaker: &Speaker,
+) -> Entity {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(5.0)),
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(TableItem)
+        .insert(Portrait)
+        .with_children(|np| {
+            np.spawn(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Px(64.0), Val::Px(64.0)),
+                    margin: UiRect::right(Val::Px(10.0)),
+                    ..Default::default()
+                },
+                image: UiImage::new(asset_server.get_handle(speaker.sprite.as_str())),
+                ..Default::default()
+            });
+            np.spawn(TextBundle::from_sections(vec![build_section(
+                &Message::new(&speaker.name, MessageStyle::Info),
+                handles.font_handle.clone(),
+                "",
+            )]));
+        })
+        .id()
+}
+
+fn typewriter_system(
+    time: Res<Time>,
+    handles: Res<AntheaHandles>,
+    mut dialogue: ResMut<Dialogue>,
+    mut text_query: Query<&mut Text, With<MessageText>>,
+) {
+    if dialogue.tick(time.delta().as_millis() as u64) {
+        for mut text in text_query.iter_mut() {
+            text.sections = page_sections(&dialogue, handles.font_handle.clone());
+        }
+    }
+}
+
+/// A click or the space or enter key shows the whole page, then the next one
+pub fn advance_dialogue(
+    mouse_button_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    handles: Res<AntheaHandles>,
+    mut dialogue: ResMut<Dialogue>,
+    mut text_query: Query<&mut Text, With<MessageText>>,
+) {
+    if (mouse_button_input.just_pressed(MouseButton::Left)
+        || keyboard_input.any_just_pressed([KeyCode::Space, KeyCode::Return]))
+        && dialogue.advance()
+    {
+        for mut text in text_query.iter_mut() {
+            text.sections = page_sections(&dialogue, handles.font_handle.clone());
+        }
+    }
+}
+
 fn build_interaction<S1: Into<String>>(parent: &mut ChildBuilder, ts: TextSection, code: S1) {
     parent
         .spawn(
@@ -460,6 +700,10 @@ fn message_decoration_system(
         (&CalculatedSize, &Transform),
         (Without<Background>, Without<MessageFramePart>),
     >,
+    camera_query: Query<
+        &Transform,
+        (With<MainCamera>, Without<Background>, Without<MessageFramePart>),
+    >,
     mut msg_query: ParamSet<(
         Query<(
             &Background,
@@ -475,8 +719,15 @@ fn message_decoration_system(
         )>,
     )>,
 ) {
+    // the frame is made of world sprites, so follow the camera
+    let (cam_x, cam_y) = camera_query
+        .iter()
+        .next()
+        .map(|c| (c.translation.x, c.translation.y))
+        .unwrap_or_default();
     for (t, cs, ttr, _mt) in text_query.iter() {
-        if !t.sections.is_empty() && !t.sections[0].value.is_empty() {
+        // a dialogue page starts with nothing revealed
+        if t.sections.iter().any(|s| !s.value.is_empty()) {
             // println!("CalculatedSize: {:?}",cs);
             let mut max_w: f32 = 0.0;
             let mut add_y = 0.0;
@@ -518,9 +769,9 @@ fn message_decoration_system(
                 *v = Visibility::Visible;
                 tr.scale.x = (w + 20.0) / 512.0;
                 tr.scale.y = (h + 20.0) / 512.0;
-                tr.translation.x = ttr.translation.x;
+                tr.translation.x = ttr.translation.x + cam_x;
                 //println!("background transform: {:?}",(-ttr.translation.y - add_y / 2.0));
-                tr.translation.y = -ttr.translation.y - add_y / 2.0;
+                tr.translation.y = -ttr.translation.y - add_y / 2.0 + cam_y;
                 tr.translation.z = z;
                 //gtr.scale = tr.scale;
                 //gtr.translation = tr.translation;
@@ -576,8 +827,8 @@ fn message_decoration_system(
                         tr.translation.z = z;
                     }
                 }
-                tr.translation.x += ttr.translation.x;
-                tr.translation.y -= ttr.translation.y;
+                tr.translation.x += ttr.translation.x + cam_x;
+                tr.translation.y -= ttr.translation.y - cam_y;
                 tr.translation.y -= add_y / 2.0;
                 //gtr.translation = tr.translation;
                 //gtr.scale = tr.scale;
@@ -589,6 +840,7 @@ fn message_decoration_system(
 fn message_clear_system(
     mut commands: Commands,
     mut event_reader: EventReader<ClearMessage>,
+    mut dialogue: ResMut<Dialogue>,
     msg_query: ParamSet<(
         Query<(&Background, &mut Visibility)>,
         Query<(&MessageFramePart, &mut Visibility)>,
@@ -600,6 +852,7 @@ fn message_clear_system(
 ) {
     if let Some(_ev) = event_reader.iter().next() {
         //println!("clear");
+        *dialogue = Dialogue::default();
         clear(
             &mut commands,
             msg_query,
@@ -641,3 +894,56 @@ fn clear(
         commands.entity(e).despawn_recursive();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate() {
+        assert_eq!(vec!["Hello"], paginate("Hello", 10, 2));
+        assert_eq!(
+            vec!["one two\nthree", "four"],
+            paginate("one two three four", 9, 2)
+        );
+        // explicit line breaks are kept
+        assert_eq!(vec!["a\nb c"], paginate("a\nb c", 9, 2));
+        // a word longer than a line is not cut
+        assert_eq!(vec!["abcdefghijk"], paginate("abcdefghijk", 5, 2));
+    }
+
+    #[test]
+    fn test_dialogue() {
+        let mut dialogue = Dialogue {
+            pages: paginate("A long line that does not fit.", 12, 1),
+            ..Default::default()
+        };
+        assert_eq!(3, dialogue.pages.len());
+        assert!(dialogue.is_waiting());
+        assert!(dialogue.tick(TYPEWRITER_DELAY * 3));
+        assert_eq!(3, dialogue.revealed);
+        assert!(!dialogue.is_complete());
+        // the first click shows the whole page, the second one the next page
+        assert!(dialogue.advance());
+        assert!(dialogue.is_complete());
+        assert!(!dialogue.tick(TYPEWRITER_DELAY));
+        assert!(dialogue.advance());
+        assert_eq!(1, dialogue.page);
+        assert_eq!(0, dialogue.revealed);
+        dialogue.advance();
+        dialogue.advance();
+        dialogue.advance();
+        assert!(!dialogue.is_waiting());
+        assert!(!dialogue.advance());
+    }
+
+    #[test]
+    fn test_is_dialogue() {
+        assert!(MessageEvent::new("Hello", MessageStyle::Info).is_dialogue());
+        assert!(!MessageEvent::new_multi(vec![
+            Message::new("Menu", MessageStyle::MenuTitle),
+            Message::new("Item", MessageStyle::Interaction("item".into())),
+        ])
+        .is_dialogue());
+    }
+}
@@ -9,18 +9,27 @@ pub struct Area {
     pub name: String,
     pub map_index: usize,
     pub start: SpritePosition,
+    pub visibility: i32,
+    // whether the player can move in eight directions instead of four
+    pub diagonal: bool,
     pub rooms: HashMap<String, Room>,
     pub affordances: HashMap<SpritePosition, Affordance>,
     pub items: HashMap<SpritePosition, Item>,
     pub characters: HashMap<SpritePosition, Character>,
 }
 
+/// How to build an area from scratch, to start a new game
+#[derive(Resource)]
+pub struct AreaDefinition(pub fn() -> Area);
+
 impl Area {
     pub fn new<S: Into<String>>(name: S, map_index: usize, start: SpritePosition) -> Self {
         Self {
             name: name.into(),
             map_index,
             start,
+            visibility: VISIBILITY_DISTANCE,
+            diagonal: false,
             rooms: HashMap::new(),
             affordances: HashMap::new(),
             items: HashMap::new(),
@@ -37,6 +46,13 @@ impl Area {
         self.rooms.values().find(|r| r.contains(pos))
     }
 
+    /// How far the player sees from the given position
+    pub fn visibility_from_position(&self, pos: &SpritePosition) -> i32 {
+        self.room_from_position(pos)
+            .and_then(|r| r.visibility)
+            .unwrap_or(self.visibility)
+    }
+
     /*pub fn room_from_coords(&self, x: f32, y: f32) -> Option<&Room> {
         self.room_from_position(&Position::new(x as i32, y as i32))
     }*/
@@ -66,6 +82,24 @@ impl Area {
         self.characters.get(pos)
     }
 
+    pub fn character_from_name<'a>(&'a self, name: &str) -> Option<&'a Character> {
+        self.characters.values().find(|c| c.name == name)
+    }
+
+    pub fn move_character(&mut self, from: &SpritePosition, to: &SpritePosition) -> &mut Self {
+        if let Some(mut chr) = self.characters.remove(from) {
+            chr.position = to.clone();
+            self.characters.insert(to.clone(), chr);
+        }
+        self
+    }
+
+    /// Remove the characters with that name, leaving the others where they are
+    pub fn remove_character(&mut self, name: &str) -> &mut Self {
+        self.characters.retain(|_, c| c.name != name);
+        self
+    }
+
     /*pub fn character_from_coords(&self, x: f32, y: f32) -> Option<&Character> {
         self.character_from_position(&Position::new(x as i32, y as i32))
     }*/
@@ -97,6 +131,7 @@ pub struct Room {
     pub name: String,
     pub description: String,
     pub dimensions: Vec<SpriteDimension>,
+    pub visibility: Option<i32>,
 }
 
 impl Room {
@@ -112,6 +147,7 @@ impl Room {
             name: name.into(),
             description: description.into(),
             dimensions: vec![SpriteDimension::new(SpritePosition::new(x1, y1), SpritePosition::new(x2, y2))],
+            visibility: None,
         }
     }
 
@@ -120,6 +156,11 @@ impl Room {
         self
     }
 
+    pub fn with_visibility(mut self, visibility: i32) -> Self {
+        self.visibility = Some(visibility);
+        self
+    }
+
     pub fn contains(&self, pos: &SpritePosition) -> bool {
         self.dimensions.iter().any(|d| d.contains(pos))
     }
@@ -150,15 +191,19 @@ impl Affordance {
 #[derive(Debug, Clone)]
 pub struct AffordanceEvent(pub String);
 
+/// An item was consumed, or equipped, when picked up
 #[derive(Debug, Clone)]
 pub struct ItemEvent(pub String);
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Serialize, Deserialize, Component)]
 pub struct Character {
     pub name: String,
     pub description: String,
     pub sprite: String,
     pub position: SpritePosition,
+    pub behaviour: Behaviour,
+    #[serde(default)]
+    pub kind: CharacterKind,
 }
 
 impl Character {
@@ -174,9 +219,62 @@ impl Character {
             description: description.into(),
             sprite: sprite.into(),
             position: SpritePosition::new(x1, y1),
+            behaviour: Behaviour::Still,
+            kind: CharacterKind::Person,
         }
     }
+
+    pub fn with_behaviour(mut self, behaviour: Behaviour) -> Self {
+        self.behaviour = behaviour;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: CharacterKind) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+/// What talent is needed to deal with a character
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CharacterKind {
+    #[default]
+    Person,
+    Animal,
+}
+
+/// How a character moves around
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Behaviour {
+    // stays at the same position
+    #[default]
+    Still,
+    // walks around at random inside the named room
+    Wander(String),
+    // walks from one position to the next, and back to the first one
+    Patrol(Vec<SpritePosition>),
+    // goes to a position depending on the time of day, from the given hour onwards
+    Schedule(Vec<(u32, SpritePosition)>),
 }
 
 #[derive(Debug, Clone)]
 pub struct CharacterEvent(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_character() {
+        let mut area = Area::new("test", 0, SpritePosition::new(0, 0));
+        area.add_character(Character::new("rats", "Big rats", "", 1, 1))
+            .add_character(Character::new("maid", "A maid", "", 2, 2));
+        area.remove_character("rats");
+        assert_eq!(1, area.characters.len());
+        assert_eq!(
+            Some("maid"),
+            area.character_from_position(&SpritePosition::new(2, 2))
+                .map(|c| c.name.as_str())
+        );
+    }
+}